@@ -0,0 +1,212 @@
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use clap::Parser;
+use tracing::debug;
+
+use crate::{history::History, notify::Notifier};
+
+const HOUR: u64 = 60 * 60;
+// how often the current hour is checked for spikes
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Parser)]
+pub struct AnomalyArgs {
+    /// Anomaly factor.
+    ///
+    /// Alert when connections or handshakes in an hour go over this many times the usual
+    /// for that hour of the day, or drop under it by as much. Handshakes are connections
+    /// that got as far as a ConnectRequest, so scans & actual clients can be told apart.
+    /// Alerts go to the notification webhooks as well as anywhere else alerts go.
+    /// (no anomaly detection if unset, must be over 1)
+    #[arg(
+        id = "anomaly_factor",
+        value_name = "FACTOR",
+        env = "ANOMALY_FACTOR",
+        long = "anomaly-factor",
+        value_parser = parse_factor
+    )]
+    factor: Option<f64>,
+
+    /// Anomaly baseline days.
+    ///
+    /// How many days of the same hour make up the usual rate, the baseline only covers
+    /// hours since startup.
+    #[arg(
+        id = "anomaly_baseline_days",
+        value_name = "DAYS",
+        env = "ANOMALY_BASELINE_DAYS",
+        long = "anomaly-baseline-days",
+        default_value_t = 7,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    baseline_days: u64,
+
+    /// Anomaly minimum.
+    ///
+    /// Rates under this are never an anomaly, so a quiet hour going from 1 connection to 5
+    /// doesn't set anything off.
+    /// (in connections per hour)
+    #[arg(
+        id = "anomaly_minimum",
+        value_name = "MINIMUM",
+        env = "ANOMALY_MINIMUM",
+        long = "anomaly-minimum",
+        default_value_t = 20
+    )]
+    minimum: u64,
+}
+
+fn parse_factor(value: &str) -> Result<f64, String> {
+    let factor: f64 = value.parse().map_err(|error| format!("{error}"))?;
+    if factor > 1.0 && factor.is_finite() {
+        Ok(factor)
+    } else {
+        Err("must be a number over 1".to_owned())
+    }
+}
+
+/// One of the rates being watched.
+struct Metric {
+    name: &'static str,
+    /// So far this hour.
+    current: u64,
+    /// Whether this hour's already been alerted on.
+    alerted: bool,
+    /// Past counts for each hour of the day (UTC), oldest first.
+    history: [VecDeque<u64>; 24],
+}
+
+impl Metric {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            current: 0,
+            alerted: false,
+            history: Default::default(),
+        }
+    }
+
+    /// Usual count for `hour` of the day, none until there's been one to go off.
+    fn baseline(&self, hour: usize) -> Option<f64> {
+        let history = &self.history[hour];
+        (!history.is_empty()).then(|| history.iter().sum::<u64>() as f64 / history.len() as f64)
+    }
+
+    /// Message for an alert if the hour's so far already well over the baseline.
+    /// The baseline's for a whole hour, so only spikes can be caught before it's over.
+    fn spike(&self, args: &AnomalyArgs, factor: f64, hour: usize) -> Option<(f64, String)> {
+        let baseline = self.baseline(hour)?;
+        (self.current >= args.minimum && self.current as f64 > baseline * factor).then(|| {
+            let message = format!(
+                "{} {} so far in the {hour:02}:00 UTC hour, usually {baseline:.0} in the whole hour",
+                self.current, self.name
+            );
+            (baseline, message)
+        })
+    }
+
+    /// Message for an alert if the whole hour came in well under the baseline.
+    fn drop(&self, args: &AnomalyArgs, factor: f64, hour: usize) -> Option<(f64, String)> {
+        let baseline = self.baseline(hour)?;
+        (baseline >= args.minimum as f64 && (self.current as f64) * factor < baseline).then(|| {
+            let message = format!(
+                "Only {} {} in the {hour:02}:00 UTC hour, usually {baseline:.0}",
+                self.current, self.name
+            );
+            (baseline, message)
+        })
+    }
+
+    /// Add the hour that's just finished to the baseline & start a new one.
+    fn finish(&mut self, args: &AnomalyArgs, hour: usize, complete: bool) {
+        // an hour that was only partly watched would drag the baseline down
+        if complete {
+            let history = &mut self.history[hour];
+            if history.len() as u64 >= args.baseline_days {
+                history.pop_front();
+            }
+            history.push_back(self.current);
+        }
+
+        self.current = 0;
+        self.alerted = false;
+    }
+}
+
+/// Keep a baseline of connections & handshakes for each hour of the day,
+/// alerting when the current hour's way over or under it.
+pub async fn run(args: &AnomalyArgs, history: Arc<History>, notifier: Option<Arc<Notifier>>) {
+    let Some(factor) = args.factor else {
+        return;
+    };
+
+    let mut metrics = [Metric::new("connections"), Metric::new("handshakes")];
+
+    // only count from startup
+    history.take_rates();
+    let mut hour = current_hour();
+    // the first hour's started before there was anything watching it
+    let mut complete = false;
+
+    let mut ticks = tokio::time::interval(CHECK_INTERVAL);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+
+        let rates = history.take_rates();
+        metrics[0].current += rates.connections;
+        metrics[1].current += rates.handshakes;
+
+        // anything counted just before the hour change lands in the hour that finished, close enough
+        let now = current_hour();
+        let hour_of_day = (hour % 24) as usize;
+        let mut anomalies = Vec::new();
+
+        for metric in &mut metrics {
+            if !metric.alerted {
+                let mut anomaly = metric.spike(args, factor, hour_of_day);
+                if anomaly.is_none() && now != hour && complete {
+                    anomaly = metric.drop(args, factor, hour_of_day);
+                }
+
+                if let Some((baseline, message)) = anomaly {
+                    history.rate_anomaly(metric.name, metric.current, baseline, &message);
+                    metric.alerted = true;
+                    anomalies.push(message);
+                }
+            }
+
+            if now != hour {
+                metric.finish(args, hour_of_day, complete);
+            }
+        }
+
+        if now != hour {
+            debug!("Finished counting the {hour_of_day:02}:00 UTC hour for anomalies.");
+            // skipped hours (suspended, clock changes) are just left out
+            complete = now == hour + 1;
+            hour = now;
+        }
+
+        if let Some(notifier) = &notifier {
+            for message in anomalies {
+                notifier
+                    .notify("Bottled Honey rate anomaly", &message)
+                    .await;
+            }
+        }
+    }
+}
+
+/// Hours since the unix epoch.
+fn current_hour() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / HOUR
+}
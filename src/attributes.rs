@@ -0,0 +1,100 @@
+use std::sync::OnceLock;
+
+use clap::Parser;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+static FILTER: OnceLock<AttributeFilter> = OnceLock::new();
+
+#[derive(Debug, Parser)]
+pub struct AttributeArgs {
+    /// Span attribute allowlist.
+    ///
+    /// Only record these harvested attributes onto spans, everything is recorded if empty.
+    /// Names ending in ".*" match everything under them, like "behavior.*".
+    /// (expects the format of "name,name")
+    #[arg(
+        env = "SPAN_ATTRIBUTES_ALLOW",
+        long = "span-attributes-allow",
+        value_delimiter = ','
+    )]
+    allow: Vec<String>,
+
+    /// Span attribute denylist.
+    ///
+    /// Never record these attributes onto spans, takes priority over the allowlist.
+    /// Denied packet fields, like "password", are also left out of transcripts & the raw
+    /// bytes of the packet they came from are left out of raw packet events & transcripts.
+    /// Deny "raw_packet" to leave out raw packet events entirely.
+    /// (expects the format of "name,name")
+    #[arg(
+        env = "SPAN_ATTRIBUTES_DENY",
+        long = "span-attributes-deny",
+        value_delimiter = ','
+    )]
+    deny: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct AttributeFilter {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl AttributeFilter {
+    fn allows(&self, name: &str) -> bool {
+        let matches = |pattern: &String| match pattern.strip_suffix(".*") {
+            Some(prefix) => name
+                .strip_prefix(prefix)
+                .is_some_and(|rest| rest.starts_with('.')),
+            None => pattern == name,
+        };
+
+        !self.deny.iter().any(matches) && (self.allow.is_empty() || self.allow.iter().any(matches))
+    }
+}
+
+/// Set up the attribute filter, should only be called once at startup.
+pub fn init(args: &AttributeArgs) {
+    let _ = FILTER.set(AttributeFilter {
+        allow: args.allow.clone(),
+        deny: args.deny.clone(),
+    });
+}
+
+/// Whether the filter allows `name`, for anything recorded other than through [`Recorder`].
+pub fn allowed(name: &str) -> bool {
+    FILTER.get().map_or(true, |filter| filter.allows(name))
+}
+
+/// Records harvested attributes onto a span, anything not allowed by the filter is dropped.
+///
+/// Everything recorded about a client should go through this rather than
+/// straight onto the span.
+#[derive(Clone, Copy)]
+pub struct Recorder<'a>(&'a Span);
+
+impl<'a> Recorder<'a> {
+    pub fn new(span: &'a Span) -> Self {
+        Self(span)
+    }
+
+    /// Record a field declared on the span.
+    pub fn record<V: tracing::Value>(self, name: &str, value: V) -> Self {
+        if allowed(name) {
+            self.0.record(name, value);
+        }
+
+        self
+    }
+
+    /// Set an attribute straight onto the opentelemetry span,
+    /// for attributes that aren't declared as fields or that tracing can't record, like arrays.
+    pub fn set_attribute(self, name: &'static str, value: impl Into<opentelemetry::Value>) -> Self {
+        if allowed(name) {
+            self.0.set_attribute(name, value);
+        }
+
+        self
+    }
+}
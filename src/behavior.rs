@@ -0,0 +1,112 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Instant,
+};
+
+use tracing::Span;
+
+use crate::attributes::Recorder;
+
+/// Summary of what a client got up to after spawning in.
+#[derive(Debug)]
+pub struct BehaviorProfile {
+    started: Instant,
+    /// Count of each packet id received.
+    pub packets: BTreeMap<u8, u32>,
+    pub movement_updates: u32,
+    /// Total distance moved in pixels, as reported by the client.
+    pub distance: f32,
+    last_position: Option<(f32, f32)>,
+    pub projectiles: u32,
+    pub projectile_types: BTreeSet<i16>,
+    pub chat_messages: u32,
+    /// Chat sent both ways, in the order it was sent.
+    pub chat: Vec<String>,
+}
+
+impl BehaviorProfile {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            packets: BTreeMap::new(),
+            movement_updates: 0,
+            distance: 0.0,
+            last_position: None,
+            projectiles: 0,
+            projectile_types: BTreeSet::new(),
+            chat_messages: 0,
+            chat: Vec::new(),
+        }
+    }
+
+    pub fn packet(&mut self, id: u8) {
+        *self.packets.entry(id).or_default() += 1;
+    }
+
+    pub fn movement(&mut self, x: f32, y: f32) {
+        self.movement_updates += 1;
+
+        if let Some((last_x, last_y)) = self.last_position.replace((x, y)) {
+            let distance = ((x - last_x).powi(2) + (y - last_y).powi(2)).sqrt();
+            // a single garbage position would poison the total
+            if distance.is_finite() {
+                self.distance += distance;
+            }
+        }
+    }
+
+    pub fn projectile(&mut self, kind: i16) {
+        self.projectiles += 1;
+        self.projectile_types.insert(kind);
+    }
+
+    pub fn chat(&mut self, message: &str) {
+        self.chat_messages += 1;
+        self.chat.push(format!("> {message}"));
+    }
+
+    pub fn chat_sent(&mut self, message: &str) {
+        self.chat.push(format!("< {message}"));
+    }
+
+    /// Per second rate of `count` over the observed duration.
+    fn rate(&self, count: u32) -> f64 {
+        let seconds = self.started.elapsed().as_secs_f64();
+        if seconds > 0.0 {
+            count as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    /// Record the profile onto `span`, expects the `behavior.*` fields to exist.
+    pub fn record(&self, span: &Span) {
+        let packets = self
+            .packets
+            .iter()
+            .map(|(id, count)| format!("{id:02x}:{count}"))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let projectile_types = self
+            .projectile_types
+            .iter()
+            .map(i16::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+
+        Recorder::new(span)
+            .record("behavior.duration", self.started.elapsed().as_secs_f64())
+            .record("behavior.packets", packets)
+            .record(
+                "behavior.packet_rate",
+                self.rate(self.packets.values().sum()),
+            )
+            .record("behavior.movement_rate", self.rate(self.movement_updates))
+            .record("behavior.distance", self.distance)
+            .record("behavior.projectile_rate", self.rate(self.projectiles))
+            .record("behavior.projectile_types", projectile_types)
+            .record("behavior.chat_messages", self.chat_messages)
+            .record("behavior.chat", self.chat.join("\n"));
+    }
+}
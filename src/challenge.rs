@@ -0,0 +1,84 @@
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use tracing::Span;
+
+use crate::attributes::Recorder;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ChallengeKind {
+    /// Ask for the server password again.
+    Password,
+    /// Ask for a code to be typed into chat.
+    ChatCode,
+}
+
+/// A fake verification request sent after spawning, and how the client answered it.
+#[derive(Debug)]
+pub struct Challenge {
+    pub kind: ChallengeKind,
+    pub code: Option<String>,
+    issued: Instant,
+    pub response: Option<String>,
+    pub response_time: Option<Duration>,
+    /// Whether the response looks like what a real player would send,
+    /// the right code or any password at all.
+    pub passed: bool,
+}
+
+impl Challenge {
+    pub fn new(kind: ChallengeKind) -> Self {
+        let code = match kind {
+            ChallengeKind::Password => None,
+            ChallengeKind::ChatCode => Some(fastrand::u32(1000..10000).to_string()),
+        };
+
+        Self {
+            kind,
+            code,
+            issued: Instant::now(),
+            response: None,
+            response_time: None,
+            passed: false,
+        }
+    }
+
+    /// Chat message explaining the challenge, if there is one.
+    pub fn prompt(&self) -> Option<String> {
+        self.code.as_ref().map(|code| {
+            format!("[Anti-Cheat] Please verify you are human by typing {code} in chat.")
+        })
+    }
+
+    /// Record the first response to the challenge, later ones are ignored.
+    pub fn respond(&mut self, response: &str) {
+        if self.response.is_some() {
+            return;
+        }
+
+        self.passed = match &self.code {
+            Some(code) => response.trim() == code,
+            None => !response.is_empty(),
+        };
+        self.response = Some(response.to_owned());
+        self.response_time = Some(self.issued.elapsed());
+    }
+
+    /// Record the challenge onto `span`, expects the `challenge.*` fields to exist.
+    pub fn record(&self, span: &Span) {
+        let kind = match self.kind {
+            ChallengeKind::Password => "password",
+            ChallengeKind::ChatCode => "chat_code",
+        };
+
+        Recorder::new(span)
+            .record("challenge.kind", kind)
+            .record("challenge.code", &self.code)
+            .record("challenge.response", &self.response)
+            .record(
+                "challenge.response_time",
+                self.response_time.map(|time| time.as_secs_f64()),
+            )
+            .record("challenge.passed", self.passed);
+    }
+}
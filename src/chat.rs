@@ -0,0 +1,30 @@
+use regex::Regex;
+
+use crate::packet;
+
+/// Canned response sent when a chat message matches `pattern`.
+#[derive(Debug, Clone)]
+pub struct ChatResponse {
+    pub pattern: Regex,
+    pub response: String,
+}
+
+/// Parse a chat response in the format of "pattern=>response".
+pub fn parse_chat_response(value: &str) -> Result<ChatResponse, String> {
+    let (pattern, response) = value
+        .split_once("=>")
+        .ok_or_else(|| "expected the format of \"pattern=>response\"".to_owned())?;
+
+    Ok(ChatResponse {
+        pattern: Regex::new(pattern).map_err(|error| error.to_string())?,
+        response: packet::parse_string(response)?,
+    })
+}
+
+/// Find the response for the first pattern matching `message`.
+pub fn respond<'a>(responses: &'a [ChatResponse], message: &str) -> Option<&'a str> {
+    responses
+        .iter()
+        .find(|response| response.pattern.is_match(message))
+        .map(|response| response.response.as_str())
+}
@@ -1,24 +1,186 @@
-use std::{net::SocketAddr, time::Duration};
+use std::{
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-use color_eyre::eyre::eyre;
 use tokio::{
-    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
     net::TcpStream,
 };
-use tracing::{debug, field, trace, trace_span, warn, Instrument, Span};
+use tracing::{debug, field, info, trace, trace_span, warn, Instrument, Span};
+
+use crate::{
+    attributes::{self, Recorder},
+    behavior::BehaviorProfile,
+    challenge::{Challenge, ChallengeKind},
+    chat,
+    error::SessionError,
+    history::{History, Seen, Token},
+    interest::{Interest, Signal},
+    modloader::{self, ModSync},
+    packet,
+    probe::{self, MalformedPoint, ProbeResults, Reaction},
+    schedule::Persona,
+    transcript::{raw_packet_event, PacketFields, Transcript},
+    Args, Engagement,
+};
 
 enum State {
     InitialConnection,
-    ReceivingPassword {
-        version: String,
-    },
-    ReveivingInfo {
-        version: String,
-        password: Option<String>,
-        name: Option<String>,
-        uuid: Option<String>,
+    ReceivingPassword,
+    /// Only reached by tModLoader clients when there's a fake mod list,
+    /// waiting on the client to finish syncing mods.
+    SyncingMods,
+    ReveivingInfo,
+    /// Only reached when engaging, waiting on the client to send PlayerSpawn.
+    Spawning,
+    /// Only reached when tarpitting, the client's going to get the world very slowly.
+    Tarpit,
+    /// Client has spawned in, watching what it does until the observation window ends.
+    InGame {
+        until: Instant,
     },
+    /// Nothing left to do with the client.
+    Finished,
+}
+
+/// Everything decided about a connection before talking to it.
+pub struct Session {
+    pub id: String,
+    pub peer_addr: SocketAddr,
+    /// Address the client connected to.
+    pub local_addr: SocketAddr,
+    pub persona: Persona,
+    pub world: packet::World,
+}
+
+/// Everything scraped from the client over the course of the connection.
+#[derive(Debug, Default)]
+pub struct ClientInfo {
+    pub version: Option<String>,
+    /// Connected with a tModLoader signature, version is then the tModLoader version.
+    pub tmodloader: bool,
+    /// Only present if the client was sent the fake mod list.
+    pub mods: Option<ModSync>,
+    /// Anything sent after the ConnectRequest signature, newer & unofficial clients
+    /// tack platform details on the end.
+    pub connect_extension: Option<Bytes>,
+    pub player_flags: Option<PlayerFlags>,
+    pub password: Option<String>,
+    pub name: Option<String>,
+    pub uuid: Option<String>,
+    /// Client version & player name, as counted towards rare fingerprints.
+    pub fingerprint: Option<String>,
+    /// Spawn tile coordinates sent with RequestEssentialTiles.
+    pub requested_spawn: Option<(i32, i32)>,
+    pub spawn: Option<PlayerSpawn>,
+    /// Only present if the client made it in game and was observed.
+    pub behavior: Option<BehaviorProfile>,
+    pub challenge: Option<Challenge>,
+    /// How interesting the session scored, only if scoring's turned on.
+    pub interest: Option<u32>,
+    /// Only present if the client was probed.
+    pub probes: Option<ProbeResults>,
+    /// Only present if the client was sent a malformed packet.
+    pub malformed: Option<Reaction>,
+    pub traffic: Arc<Traffic>,
+}
+
+/// Bytes sent & received over the connection,
+/// shared with the reader & writer so it's counted as it goes.
+#[derive(Debug, Default)]
+pub struct Traffic {
+    pub sent: AtomicU64,
+    pub received: AtomicU64,
+}
+
+/// Difficulty & progression flags from a PlayerInfo ($04) packet, as of 1.4.4.
+#[derive(Debug, Clone)]
+pub struct PlayerFlags {
+    pub difficulty: &'static str,
+    /// Every other flag that was set.
+    pub flags: Vec<&'static str>,
+    /// Anything after the flags, only newer clients send more.
+    pub extension: Option<Bytes>,
+}
+
+impl PlayerFlags {
+    // hair dye, hidden accessories, hidden misc slots & 7 colours come between the name & flags
+    const SKIPPED: usize = 1 + 2 + 1 + 7 * 3;
+
+    // the difficulty byte's extra accessory bit, then each bit of the two bytes after it
+    const FLAGS: [&'static [&'static str]; 3] = [
+        &["", "", "extra_accessory"],
+        &[
+            "biome_torches",
+            "happy_fun_torch_time",
+            "unlocked_biome_torches",
+            "unlocked_super_cart",
+            "enabled_super_cart",
+        ],
+        &[
+            "used_aegis_crystal",
+            "used_aegis_fruit",
+            "used_arcane_crystal",
+            "used_galaxy_pearl",
+            "used_gummy_worm",
+            "used_ambrosia",
+            "ate_artisan_bread",
+        ],
+    ];
+
+    /// Read the flags from what's left of the packet after the player name.
+    fn read(body: &mut Bytes) -> Result<Self, SessionError> {
+        check_remaining(body, Self::SKIPPED + 3)?;
+        body.advance(Self::SKIPPED);
+
+        let bytes = [body.get_u8(), body.get_u8(), body.get_u8()];
+        let difficulty = match bytes[0] {
+            byte if byte & 0x08 != 0 => "journey",
+            byte if byte & 0x02 != 0 => "hardcore",
+            byte if byte & 0x01 != 0 => "mediumcore",
+            _ => "classic",
+        };
+
+        let flags = Self::FLAGS
+            .iter()
+            .zip(bytes)
+            .flat_map(|(names, byte)| {
+                names
+                    .iter()
+                    .enumerate()
+                    .filter(move |(bit, name)| !name.is_empty() && byte & (1 << bit) != 0)
+                    .map(|(_, name)| *name)
+            })
+            .collect();
+
+        let extension = (!body.is_empty()).then(|| body.split_off(0));
+
+        Ok(Self {
+            difficulty,
+            flags,
+            extension,
+        })
+    }
+}
+
+/// Contents of a PlayerSpawn ($0C) packet.
+#[derive(Debug, Clone, Copy)]
+pub struct PlayerSpawn {
+    pub player_id: u8,
+    pub x: i16,
+    pub y: i16,
+    pub respawn_timer: i32,
+    pub deaths_pve: i16,
+    pub deaths_pvp: i16,
+    pub context: u8,
 }
 
 fn check_zero_remaining(source: &Bytes) {
@@ -30,9 +192,60 @@ fn check_zero_remaining(source: &Bytes) {
     }
 }
 
+fn check_remaining(source: &Bytes, length: usize) -> Result<(), SessionError> {
+    if source.len() < length {
+        return Err(SessionError::PacketTooShort);
+    }
+
+    Ok(())
+}
+
 fn get_length_prefixed_bytes(source: &mut impl Buf) -> Bytes {
-    let length = source.get_u8();
-    source.copy_to_bytes(length as _)
+    // strings are prefixed with a 7 bit encoded length
+    let mut length = 0;
+    for shift in (0..35).step_by(7) {
+        if !source.has_remaining() {
+            break;
+        }
+
+        let byte = source.get_u8();
+        length |= ((byte & 0x7f) as usize) << shift;
+
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    // garbage lengths just get whatever's left rather than panicking
+    source.copy_to_bytes(length.min(source.remaining()))
+}
+
+/// Reader that counts everything read through it as received.
+struct CountedReader<R> {
+    inner: R,
+    traffic: Arc<Traffic>,
+}
+
+impl<R> AsyncRead for CountedReader<R>
+where
+    R: Unpin,
+    R: AsyncRead,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(())) = poll {
+            let read = (buf.filled().len() - before) as u64;
+            self.traffic.received.fetch_add(read, Ordering::Relaxed);
+        }
+
+        poll
+    }
 }
 
 async fn read_timeout<R>(
@@ -49,6 +262,63 @@ where
         .await?
 }
 
+/// Sends packets to the client, keeping track of how many bytes have been sent
+/// and recording the total onto the span it was created in once dropped.
+struct ClientWriter<W> {
+    inner: W,
+    traffic: Arc<Traffic>,
+    span: Span,
+    transcript: Option<Transcript>,
+    /// Byte limit for raw packet span events, if they're enabled.
+    raw_events: Option<usize>,
+}
+
+impl<W> ClientWriter<W>
+where
+    W: Unpin,
+    W: AsyncWrite,
+{
+    fn new(
+        inner: W,
+        traffic: Arc<Traffic>,
+        transcript: Option<Transcript>,
+        raw_events: Option<usize>,
+    ) -> Self {
+        Self {
+            inner,
+            traffic,
+            span: Span::current(),
+            transcript,
+            raw_events,
+        }
+    }
+
+    async fn send(&mut self, packet: &'static str, data: &[u8]) -> std::io::Result<()> {
+        write_all_timeout(&mut self.inner, data)
+            .instrument(trace_span!("client.write", packet))
+            .await?;
+
+        self.traffic
+            .sent
+            .fetch_add(data.len() as u64, Ordering::Relaxed);
+        if let Some(transcript) = &mut self.transcript {
+            transcript.sent(packet, data);
+        }
+        if let Some(limit) = self.raw_events {
+            raw_packet_event(&self.span, "sent", data, limit);
+        }
+
+        Ok(())
+    }
+}
+
+impl<W> Drop for ClientWriter<W> {
+    fn drop(&mut self) {
+        let sent = self.traffic.sent.load(Ordering::Relaxed);
+        Recorder::new(&self.span).record("bytes_sent", sent);
+    }
+}
+
 async fn write_all_timeout<W>(writer: &mut W, src: &[u8]) -> std::io::Result<()>
 where
     W: Unpin,
@@ -64,32 +334,261 @@ where
     std::result::Result::Ok(())
 }
 
+/// Slowly stream junk sections to the client at `rate` bytes per second,
+/// only stops once writing fails, usually because the client gave up.
+async fn tarpit<W>(writer: &mut ClientWriter<W>, world: &packet::World, rate: u32) -> std::io::Error
+where
+    W: Unpin,
+    W: AsyncWrite,
+{
+    let (sections_x, sections_y) = world.sections();
+    let total_sections = sections_x as i32 * sections_y as i32;
+
+    if let Err(error) = writer
+        .send(
+            "StatusText",
+            &packet::status_text(total_sections, "Receiving tile data"),
+        )
+        .await
+    {
+        return error;
+    }
+
+    // keep packets small enough that even tiny rates send something every few seconds
+    let chunk_length = rate.clamp(1, 1024) as u16;
+
+    loop {
+        for y in 0..sections_y {
+            for x in 0..sections_x {
+                let section = packet::junk_section(x, y, chunk_length);
+
+                if let Err(error) = writer.send("SendSection", &section).await {
+                    return error;
+                }
+
+                tokio::time::sleep(Duration::from_secs_f64(
+                    section.len() as f64 / rate.max(1) as f64,
+                ))
+                .await;
+            }
+        }
+    }
+}
+
+/// Send each probe to the client & note which packets come back before the next one.
+///
+/// Anything received is left in `decode_buf` so it's still handled as usual afterwards.
+async fn probe<R, W>(
+    reader: &mut R,
+    writer: &mut ClientWriter<W>,
+    world: &packet::World,
+    decode_buf: &mut BytesMut,
+) -> Result<ProbeResults, SessionError>
+where
+    R: Unpin,
+    R: AsyncRead,
+    W: Unpin,
+    W: AsyncWrite,
+{
+    let mut results = ProbeResults::default();
+
+    // anything already buffered was sent before the probes
+    let mut scanned = 0;
+    packet_ids(decode_buf, &mut scanned);
+
+    for (name, data) in probe::probes(world) {
+        let Some(responses) = watch(reader, writer, name, &data, decode_buf, &mut scanned).await?
+        else {
+            return Err(SessionError::PeerClosed);
+        };
+
+        debug!("Probe {name} got {responses:02x?}");
+        results.push(name, responses);
+    }
+
+    Ok(results)
+}
+
+/// Send the client a malformed packet & see how it reacts.
+async fn malformed<R, W>(
+    reader: &mut R,
+    writer: &mut ClientWriter<W>,
+    decode_buf: &mut BytesMut,
+) -> Result<Reaction, SessionError>
+where
+    R: Unpin,
+    R: AsyncRead,
+    W: Unpin,
+    W: AsyncWrite,
+{
+    let mut scanned = 0;
+    packet_ids(decode_buf, &mut scanned);
+
+    let responses = watch(
+        reader,
+        writer,
+        "MalformedStatusText",
+        &packet::malformed_status_text(),
+        decode_buf,
+        &mut scanned,
+    )
+    .await?;
+
+    Ok(match responses {
+        None => Reaction::Disconnected,
+        Some(ids) if ids.is_empty() => Reaction::Ignored,
+        Some(ids) => Reaction::Answered(ids),
+    })
+}
+
+/// Send `data` then collect the ids of packets received for the probe window,
+/// none if the client disconnected.
+async fn watch<R, W>(
+    reader: &mut R,
+    writer: &mut ClientWriter<W>,
+    name: &'static str,
+    data: &[u8],
+    decode_buf: &mut BytesMut,
+    scanned: &mut usize,
+) -> Result<Option<Vec<u8>>, SessionError>
+where
+    R: Unpin,
+    R: AsyncRead,
+    W: Unpin,
+    W: AsyncWrite,
+{
+    writer.send(name, data).await?;
+
+    let mut read_buf = vec![0; 1024];
+    let mut responses = Vec::new();
+    let deadline = tokio::time::Instant::now() + probe::PROBE_WINDOW;
+
+    loop {
+        let read = tokio::time::timeout_at(deadline, reader.read(&mut read_buf))
+            .instrument(trace_span!("read"))
+            .await;
+        let Ok(read) = read else {
+            return Ok(Some(responses));
+        };
+
+        let len = match read {
+            Ok(0) => return Ok(None),
+            Err(error) if error.kind() == std::io::ErrorKind::ConnectionReset => return Ok(None),
+            len => len?,
+        };
+
+        decode_buf.put_slice(&read_buf[..len]);
+        if decode_buf.len() >= crate::MAX_BUFFER_LENGTH {
+            return Err(SessionError::OversizedBuffer);
+        }
+
+        responses.extend(packet_ids(decode_buf, scanned));
+    }
+}
+
+/// Ids of the complete packets in `buf` past `offset`, moving `offset` past them.
+fn packet_ids(buf: &[u8], offset: &mut usize) -> Vec<u8> {
+    let mut ids = Vec::new();
+
+    while let Some(header) = buf.get(*offset..*offset + 3) {
+        let length = u16::from_le_bytes([header[0], header[1]]) as usize;
+        // garbage is left for the usual handling to complain about
+        if length < 3 || buf.len() < *offset + length {
+            break;
+        }
+
+        ids.push(header[2]);
+        *offset += length;
+    }
+
+    ids
+}
+
+/// Let the client carry on connecting, asking for the password first if the persona says to.
+async fn continue_connecting<W>(
+    writer: &mut ClientWriter<W>,
+    persona: &Persona,
+) -> Result<State, SessionError>
+where
+    W: Unpin,
+    W: AsyncWrite,
+{
+    if persona.password_chance > fastrand::f32() {
+        // write RequestPassword packet
+        writer.send("RequestPassword", b"\x03\x00\x25").await?;
+
+        Ok(State::ReceivingPassword)
+    } else {
+        // write ContinueConnecting packet with a 0 player id
+        writer
+            .send("ContinueConnecting(0)", b"\x05\x00\x03\0\0")
+            .await?;
+
+        Ok(State::ReveivingInfo)
+    }
+}
+
+/// Talk to the client until there's nothing left to get out of it.
+///
+/// `info` is filled in as the connection goes, so whatever was scraped is still
+/// around if the client errors out part way through.
 pub async fn handle_client(
     stream: TcpStream,
-    _peer_addr: SocketAddr,
-    password_chance: f32,
-) -> std::io::Result<(String, Option<String>, String, String)> {
-    let (mut client_reader, mut client_writer) = stream.into_split();
+    session: &Session,
+    args: &Args,
+    history: &History,
+    info: &mut ClientInfo,
+) -> Result<(), SessionError> {
+    let Session {
+        id: session_id,
+        peer_addr,
+        persona,
+        world,
+        ..
+    } = session;
+
+    let (client_reader, client_writer) = stream.into_split();
+    let mut client_reader = CountedReader {
+        inner: client_reader,
+        traffic: info.traffic.clone(),
+    };
+
+    let transcript = args.transcript_dir.as_ref().and_then(|directory| {
+        Transcript::create(directory, session_id)
+            .inspect_err(|error| warn!("Failed to create transcript: {error}"))
+            .ok()
+    });
+    let mut client_writer = ClientWriter::new(
+        client_writer,
+        info.traffic.clone(),
+        transcript,
+        args.raw_packet_events,
+    );
+    let seen = Seen::current(peer_addr.ip(), session_id);
+    history.link_previous(&seen);
 
     // not that happy with this, may come back to it
     let mut connection_state = State::InitialConnection;
 
+    // engagement can go up part way through if the client turns out to be interesting
+    let mut interest = Interest::new(&args.interest, persona);
+
     let mut read_buf = vec![0; 64];
     let mut decode_buf = BytesMut::new();
 
     loop {
-        async {
+        let read = async {
             // give the client a little more time if they're at the password stage
-            let timeout_duration = if let State::ReceivingPassword { .. } = &connection_state {
-                // todo: need to tune this
-                Duration::from_secs(30)
-            } else {
-                crate::IDLE_TIMEOUT
+            let timeout_duration = match &connection_state {
+                State::ReceivingPassword => persona.password_timeout,
+                // real players can stand around for a while, so just wait out the window
+                State::InGame { until } => until.saturating_duration_since(Instant::now()),
+                _ => interest.idle_timeout(),
             };
 
             let len = read_timeout(timeout_duration, &mut client_reader, &mut read_buf).await?;
             if len == 0 {
-                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+                return Err(SessionError::PeerClosed);
             }
 
             decode_buf.put_slice(&read_buf[..len]);
@@ -102,114 +601,194 @@ pub async fn handle_client(
                     decode_buf.len()
                 );
 
-                return Err(std::io::Error::other(eyre!("Buffer to large")));
+                return Err(SessionError::OversizedBuffer);
             }
 
             Ok(())
         }
         .instrument(trace_span!("client.read"))
-        .await?;
+        .await;
 
-        if decode_buf.len() < 2 {
-            continue;
+        // the client leaving or going quiet while being observed is expected,
+        // it just means the observation is over
+        if let State::InGame { .. } = connection_state {
+            if let Err(error) = read {
+                debug!("Observation ended: {error}");
+                record_info(info);
+                return Ok(());
+            }
+        } else {
+            read?;
         }
 
-        let mut packet_buf = decode_buf.clone();
+        // clients tend to send a bunch of packets at once then wait for a response,
+        // so handle everything that's been buffered before reading again
+        while decode_buf.len() >= 2 {
+            let mut packet_buf = &decode_buf[..];
 
-        let packet_length = packet_buf.get_u16_le() as usize;
-        if packet_length < 3 {
-            return Err(std::io::Error::other(eyre!("Invalid packet length")));
-        }
+            let packet_length = packet_buf.get_u16_le() as usize;
+            if packet_length < 3 {
+                return Err(SessionError::InvalidLength);
+            }
+
+            // subtract length of the length from the length :)))))))
+            let data_length = packet_length - 2;
+
+            // wait for more data if we don't have the full packet yet
+            if packet_buf.len() < data_length {
+                break;
+            }
+
+            // split the packet off from the decode buffer
+            let raw = decode_buf.split_to(packet_length).freeze();
+            let mut body = raw.slice(2..);
 
-        // subtract length of the length from the length :)))))))
-        let data_length = packet_length - 2;
+            let id = body.get_u8();
+            // the body's left to raw packet events, which know what's been denied
+            trace!("> packet ${id:02x}: {} bytes", body.len());
 
-        // if we have enough data to read the full packet then split it of from the decode buffer and do that
-        if packet_buf.len() >= data_length {
-            let mut body = packet_buf.split_to(data_length).freeze();
-            // essentialy removes the current packet from the decude buffer
-            std::mem::swap(&mut packet_buf, &mut decode_buf);
+            let mut fields = PacketFields::new(
+                args.raw_packet_events
+                    .map(|limit| (client_writer.span.clone(), raw.clone(), limit)),
+            );
+            if let Some(transcript) = &mut client_writer.transcript {
+                transcript.receiving();
+            }
 
-            let id = body.get_i8();
-            trace!("> packet ${id:02x}: {body:?}");
+            if let Some(behavior) = &mut info.behavior {
+                behavior.packet(id);
+            }
 
             connection_state = match (id, connection_state) {
                 (0x01, State::InitialConnection) => {
                     async {
                         let signature = get_length_prefixed_bytes(&mut body);
                         let signature = String::from_utf8_lossy(&signature);
-                        Span::current().record("signature", &*signature);
+                        fields.record("signature", &*signature);
 
-                        check_zero_remaining(&body);
+                        if !body.is_empty() {
+                            fields.record("extension", packet::hex(&body));
+                            debug!("> ConnectRequest extension: {body:?}");
+                            info.connect_extension = Some(body.split_off(0));
+                        }
 
-                        if let Some((_, version)) = signature.split_once("Terraria") {
-                            debug!("> ConnectRequest(version: {version})");
+                        let version = match signature.strip_prefix(modloader::SIGNATURE_PREFIX) {
+                            Some(version) => {
+                                info.tmodloader = true;
+                                Some(version)
+                            }
+                            None => signature.split_once("Terraria").map(|(_, version)| version),
+                        };
+
+                        if let Some(version) = version {
+                            history.handshake();
+                            debug!(
+                                "> ConnectRequest(version: {version}, tmodloader: {})",
+                                info.tmodloader
+                            );
+                            info.version = Some(version.to_string());
 
-                            if password_chance > fastrand::f32() {
-                                // write RequestPassword packet
-                                write_all_timeout(&mut client_writer, b"\x03\x00\x25")
-                                    .instrument(trace_span!(
-                                        "client.write",
-                                        packet = "RequestPassword"
-                                    ))
+                            if persona.full {
+                                info!("Scheduled as full, kicking client.");
+                                client_writer
+                                    .send("Kick", &packet::kick("Server is full."))
                                     .await?;
 
-                                Ok(State::ReceivingPassword {
-                                    version: version.to_string(),
-                                })
-                            } else {
-                                // write ContinueConnecting packet with a 0 player id
-                                write_all_timeout(&mut client_writer, b"\x05\x00\x03\0\0")
-                                    .instrument(trace_span!(
-                                        "client.write",
-                                        packet = "ContinueConnecting(0)"
-                                    ))
+                                Ok(State::Finished)
+                            } else if info.tmodloader && !args.fake_mods.is_empty() {
+                                client_writer
+                                    .send("SyncMods", &modloader::sync_mods(&args.fake_mods))
                                     .await?;
+                                info.mods = Some(ModSync::default());
 
-                                Ok(State::ReveivingInfo {
-                                    version: version.to_string(),
-                                    password: None,
-                                    name: None,
-                                    uuid: None,
-                                })
+                                Ok(State::SyncingMods)
+                            } else {
+                                continue_connecting(&mut client_writer, persona).await
                             }
                         } else {
                             warn!("> Unknown ConnectRequest signature: {signature:?}");
-                            Err(std::io::Error::other(eyre!("Unknown signature")))
+                            Err(SessionError::UnknownSignature)
                         }
                     }
                     .instrument(trace_span!(
                         "client.handle_packet",
                         packet = "ConnectRequest",
-                        signature = field::Empty
+                        signature = field::Empty,
+                        extension = field::Empty
+                    ))
+                    .await?
+                }
+
+                (modloader::MOD_FILE, State::SyncingMods) => {
+                    async {
+                        let name = get_length_prefixed_bytes(&mut body);
+                        let name = String::from_utf8_lossy(&name);
+                        fields.record("mod_name", &*name);
+
+                        check_zero_remaining(&body);
+
+                        // can't send a mod that doesn't exist, so downloads are "turned off"
+                        debug!("> ModFile(name: {name:?})");
+                        if let Some(mods) = &mut info.mods {
+                            mods.requested = Some(name.to_string());
+                        }
+
+                        client_writer
+                            .send(
+                                "Kick",
+                                &packet::kick("Mod downloading is disabled on this server."),
+                            )
+                            .await?;
+
+                        Result::<_, SessionError>::Ok(State::Finished)
+                    }
+                    .instrument(trace_span!(
+                        "client.handle_packet",
+                        packet = "ModFile",
+                        mod_name = field::Empty
                     ))
                     .await?
                 }
 
-                (0x26, State::ReceivingPassword { version }) => {
+                // done syncing without asking for anything, so it's claiming to have every mod
+                (modloader::SYNC_MODS, State::SyncingMods) => {
+                    async {
+                        debug!("> SyncMods");
+                        if let Some(mods) = &mut info.mods {
+                            mods.installed =
+                                args.fake_mods.iter().map(ToString::to_string).collect();
+                        }
+
+                        continue_connecting(&mut client_writer, persona).await
+                    }
+                    .instrument(trace_span!("client.handle_packet", packet = "SyncMods"))
+                    .await?
+                }
+
+                (0x26, State::ReceivingPassword) => {
                     async {
                         let password = get_length_prefixed_bytes(&mut body);
                         let password = String::from_utf8_lossy(&password);
-                        Span::current().record("password", &*password);
+                        fields.record("password", &*password);
 
                         check_zero_remaining(&body);
 
-                        debug!("> SendPassword(password: {password:?})");
+                        if attributes::allowed("password") {
+                            debug!("> SendPassword(password: {password:?})");
+                        } else {
+                            debug!("> SendPassword");
+                        }
+                        info.password = Some(password.to_string());
+                        history.observe(Token::Password, &password, &seen);
+                        interest.add(Signal::Password);
+                        info.interest = interest.score();
 
                         // write ContinueConnecting packet with a 0 player id
-                        write_all_timeout(&mut client_writer, b"\x05\x00\x03\0\0")
-                            .instrument(trace_span!(
-                                "client.write",
-                                packet = "ContinueConnecting(0)"
-                            ))
+                        client_writer
+                            .send("ContinueConnecting(0)", b"\x05\x00\x03\0\0")
                             .await?;
 
-                        std::io::Result::Ok(State::ReveivingInfo {
-                            version,
-                            password: Some(password.to_string()),
-                            name: None,
-                            uuid: None,
-                        })
+                        Result::<_, SessionError>::Ok(State::ReveivingInfo)
                     }
                     .instrument(trace_span!(
                         "client.handle_packet",
@@ -219,67 +798,80 @@ pub async fn handle_client(
                     .await?
                 }
 
-                (
-                    0x04,
-                    State::ReveivingInfo {
-                        version,
-                        password,
-                        name: _,
-                        uuid,
-                    },
-                ) => {
+                (0x04, State::ReveivingInfo) => {
                     async {
+                        check_remaining(&body, 3)?;
                         let _ = body.get_u8();
                         let _ = body.get_u8();
                         let _ = body.get_u8();
 
                         let name = get_length_prefixed_bytes(&mut body);
                         let name = String::from_utf8_lossy(&name);
-                        Span::current().record("player_name", &*name);
+                        fields.record("player_name", &*name);
 
-                        // not reading the whole packet, there will definately be bytes left over
+                        // older clients & bots cutting corners won't have all of it,
+                        // the name's still worth having
+                        match PlayerFlags::read(&mut body) {
+                            Ok(flags) => {
+                                fields
+                                    .record("difficulty", flags.difficulty)
+                                    .record("flags", flags.flags.join(","));
+                                if let Some(extension) = &flags.extension {
+                                    fields.record("extension", packet::hex(extension));
+                                }
 
-                        debug!("> PlayerInfo(name: {name:?}");
+                                debug!("> PlayerInfo(name: {name:?}, flags: {flags:?})");
+                                info.player_flags = Some(flags);
+                            }
+                            Err(_) => debug!("> PlayerInfo(name: {name:?}, flags: missing)"),
+                        }
 
-                        State::ReveivingInfo {
-                            version,
-                            password,
-                            name: Some(name.to_string()),
-                            uuid,
+                        info.name = Some(name.to_string());
+                        let known_ips = history.remember(Token::PlayerName, &name, &seen);
+                        Recorder::new(&seen.span)
+                            .set_attribute("player_name.known_ips", known_ips as i64);
+                        let fingerprint = format!(
+                            "{}{} {name:?}",
+                            if info.tmodloader {
+                                modloader::SIGNATURE_PREFIX
+                            } else {
+                                "Terraria"
+                            },
+                            info.version.as_deref().unwrap_or("?")
+                        );
+                        info.fingerprint = Some(fingerprint.clone());
+                        if history.fingerprint(fingerprint) {
+                            interest.add(Signal::RareFingerprint);
+                            info.interest = interest.score();
                         }
+
+                        Result::<_, SessionError>::Ok(State::ReveivingInfo)
                     }
                     .instrument(trace_span!(
                         "client.handle_packet",
                         packet = "PlayerInfo",
-                        player_name = field::Empty
+                        player_name = field::Empty,
+                        difficulty = field::Empty,
+                        flags = field::Empty,
+                        extension = field::Empty
                     ))
-                    .await
+                    .await?
                 }
 
-                (
-                    0x44,
-                    State::ReveivingInfo {
-                        version,
-                        password,
-                        name,
-                        uuid: _,
-                    },
-                ) => {
+                (0x44, State::ReveivingInfo) => {
                     async {
                         let uuid = get_length_prefixed_bytes(&mut body);
                         let uuid = String::from_utf8_lossy(&uuid);
-                        Span::current().record("player_uuid", &*uuid);
+                        fields.record("player_uuid", &*uuid);
 
                         check_zero_remaining(&body);
 
                         debug!("> ClientUUID(uuid: {uuid:?})");
+                        info.uuid = Some(uuid.to_string());
+                        let known_ips = history.observe(Token::Uuid, &uuid, &seen);
+                        Recorder::new(&seen.span).set_attribute("uuid.known_ips", known_ips as i64);
 
-                        State::ReveivingInfo {
-                            version,
-                            password,
-                            name,
-                            uuid: Some(uuid.to_string()),
-                        }
+                        State::ReveivingInfo
                     }
                     .instrument(trace_span!(
                         "client.handle_packet",
@@ -289,25 +881,503 @@ pub async fn handle_client(
                     .await
                 }
 
+                (0x06, State::ReveivingInfo) if interest.engagement() != Engagement::None => {
+                    async {
+                        debug!("> RequestWorldData");
+
+                        client_writer
+                            .send("WorldInfo", &packet::world_info(world))
+                            .await?;
+
+                        Result::<_, SessionError>::Ok(State::ReveivingInfo)
+                    }
+                    .instrument(trace_span!(
+                        "client.handle_packet",
+                        packet = "RequestWorldData"
+                    ))
+                    .await?
+                }
+
+                // plenty of bots skip straight to this after ContinueConnecting
+                (0x08, State::ReveivingInfo) => {
+                    async {
+                        check_remaining(&body, 8)?;
+                        let x = body.get_i32_le();
+                        let y = body.get_i32_le();
+                        fields.record("spawn_x", x).record("spawn_y", y);
+
+                        check_zero_remaining(&body);
+
+                        debug!("> RequestEssentialTiles(x: {x}, y: {y})");
+                        info.requested_spawn = Some((x, y));
+
+                        let engagement = interest.engagement();
+                        if engagement == Engagement::None {
+                            return Result::<_, SessionError>::Ok(State::ReveivingInfo);
+                        }
+
+                        if engagement == Engagement::Tarpit {
+                            return Ok(State::Tarpit);
+                        }
+
+                        // skip sending any actual tiles, this is all that's
+                        // needed for the client to try spawning
+                        client_writer
+                            .send(
+                                "CompleteConnectionAndSpawn",
+                                &packet::complete_connection_and_spawn(),
+                            )
+                            .await?;
+
+                        Ok(State::Spawning)
+                    }
+                    .instrument(trace_span!(
+                        "client.handle_packet",
+                        packet = "RequestEssentialTiles",
+                        spawn_x = field::Empty,
+                        spawn_y = field::Empty
+                    ))
+                    .await?
+                }
+
+                (0x0C, State::Spawning) => {
+                    async {
+                        check_remaining(&body, 14)?;
+                        let spawn = PlayerSpawn {
+                            player_id: body.get_u8(),
+                            x: body.get_i16_le(),
+                            y: body.get_i16_le(),
+                            respawn_timer: body.get_i32_le(),
+                            deaths_pve: body.get_i16_le(),
+                            deaths_pvp: body.get_i16_le(),
+                            context: body.get_u8(),
+                        };
+
+                        fields
+                            .record("player_id", spawn.player_id)
+                            .record("spawn_x", spawn.x)
+                            .record("spawn_y", spawn.y)
+                            .record("respawn_timer", spawn.respawn_timer)
+                            .record("deaths_pve", spawn.deaths_pve)
+                            .record("deaths_pvp", spawn.deaths_pvp)
+                            .record("context", spawn.context);
+
+                        check_zero_remaining(&body);
+
+                        debug!("> {spawn:?}");
+                        info.spawn = Some(spawn);
+
+                        client_writer
+                            .send("FinishedConnectingToServer", &packet::finished_connecting())
+                            .await?;
+
+                        if let Some(motd) = &args.motd {
+                            client_writer
+                                .send("ChatMessage", &packet::chat_message(motd))
+                                .await?;
+                        }
+
+                        if args.observe_window == 0 {
+                            return Result::<_, SessionError>::Ok(State::Finished);
+                        }
+
+                        let mut behavior = BehaviorProfile::new();
+                        if let Some(motd) = &args.motd {
+                            behavior.chat_sent(motd);
+                        }
+
+                        if let Some(kind) = args.challenge {
+                            let challenge = Challenge::new(kind);
+
+                            if let Some(code) = &challenge.code {
+                                history.remember(Token::ChallengeCode, code, &seen);
+                            }
+
+                            if let Some(prompt) = challenge.prompt() {
+                                client_writer
+                                    .send("ChatMessage", &packet::chat_message(&prompt))
+                                    .await?;
+
+                                behavior.chat_sent(&prompt);
+                            }
+
+                            if kind == ChallengeKind::Password {
+                                client_writer
+                                    .send("RequestPassword", b"\x03\x00\x25")
+                                    .await?;
+                            }
+
+                            info.challenge = Some(challenge);
+                        }
+
+                        info.behavior = Some(behavior);
+                        Ok(State::InGame {
+                            until: Instant::now() + Duration::from_secs(args.observe_window),
+                        })
+                    }
+                    .instrument(trace_span!(
+                        "client.handle_packet",
+                        packet = "PlayerSpawn",
+                        player_id = field::Empty,
+                        spawn_x = field::Empty,
+                        spawn_y = field::Empty,
+                        respawn_timer = field::Empty,
+                        deaths_pve = field::Empty,
+                        deaths_pvp = field::Empty,
+                        context = field::Empty
+                    ))
+                    .await?
+                }
+
+                // only expected if we've asked for the password again
+                (0x26, state @ State::InGame { .. }) => {
+                    async {
+                        let password = get_length_prefixed_bytes(&mut body);
+                        let password = String::from_utf8_lossy(&password);
+                        fields.record("password", &*password);
+
+                        check_zero_remaining(&body);
+
+                        if attributes::allowed("password") {
+                            debug!("> SendPassword(password: {password:?})");
+                        } else {
+                            debug!("> SendPassword");
+                        }
+                        history.observe(Token::Password, &password, &seen);
+
+                        if let Some(challenge) = &mut info.challenge {
+                            challenge.respond(&password);
+                        }
+                    }
+                    .instrument(trace_span!(
+                        "client.handle_packet",
+                        packet = "SendPassword",
+                        password = field::Empty
+                    ))
+                    .await;
+
+                    state
+                }
+
+                (0x0D, state @ State::InGame { .. }) => {
+                    // player id, 4 bit flag bytes & selected item come before the position
+                    if body.len() >= 14 {
+                        body.advance(6);
+                        let x = body.get_f32_le();
+                        let y = body.get_f32_le();
+                        fields.record("x", x).record("y", y);
+
+                        if let Some(behavior) = &mut info.behavior {
+                            behavior.movement(x, y);
+                        }
+                    }
+
+                    // there's optional velocity & return position data after, don't care about it
+
+                    state
+                }
+
+                (0x1B, state @ State::InGame { .. }) => {
+                    // identity, position, velocity & owner come before the type
+                    if body.len() >= 21 {
+                        body.advance(19);
+                        let kind = body.get_i16_le();
+                        fields.record("projectile_type", kind);
+
+                        if let Some(behavior) = &mut info.behavior {
+                            behavior.projectile(kind);
+                        }
+                    }
+
+                    state
+                }
+
+                (0x52, state @ State::InGame { .. }) => {
+                    async {
+                        // only care about the text module, everything else is ignored
+                        if body.len() < 2 || body.get_u16_le() != 1 {
+                            return Ok(());
+                        }
+
+                        let command = get_length_prefixed_bytes(&mut body);
+                        let command = String::from_utf8_lossy(&command);
+                        let text = get_length_prefixed_bytes(&mut body);
+                        let text = String::from_utf8_lossy(&text);
+                        fields.record("command", &*command).record("text", &*text);
+
+                        check_zero_remaining(&body);
+
+                        debug!("> ChatMessage(command: {command:?}, text: {text:?})");
+
+                        if let Some(behavior) = &mut info.behavior {
+                            behavior.chat(&text);
+                        }
+
+                        if let Some(challenge @ Challenge { code: Some(_), .. }) =
+                            &mut info.challenge
+                        {
+                            if challenge.response.is_none() {
+                                history.check(Token::ChallengeCode, text.trim(), &seen);
+                            }
+
+                            challenge.respond(&text);
+                        }
+
+                        if let Some(response) = chat::respond(&args.chat_responses, &text) {
+                            info!("Responding to chat {text:?} with {response:?}");
+
+                            client_writer
+                                .send("ChatMessage", &packet::chat_message(response))
+                                .await?;
+
+                            if let Some(behavior) = &mut info.behavior {
+                                behavior.chat_sent(response);
+                            }
+                        }
+
+                        Result::<_, SessionError>::Ok(())
+                    }
+                    .instrument(trace_span!(
+                        "client.handle_packet",
+                        packet = "ChatMessage",
+                        command = field::Empty,
+                        text = field::Empty
+                    ))
+                    .await?;
+
+                    state
+                }
+
+                // not asked for, but it's still a password
+                (0x26, state) => {
+                    fields.skip("password");
+                    state
+                }
+
                 // don't really care that much about the information other packets can give
                 (_, state) => state,
             };
 
-            if let State::ReveivingInfo {
-                version,
-                password,
-                name: Some(name),
-                uuid: Some(uuid),
-            } = connection_state
-            {
-                Span::current()
-                    .record("version", &version)
-                    .record("password", &password)
-                    .record("player_name", &name)
-                    .record("player_uuid", &uuid);
-
-                return Ok((version, password, name, uuid));
+            if let Some(transcript) = &mut client_writer.transcript {
+                let name = packet::name(id).unwrap_or("Unknown");
+                transcript.received(id, name, &mut fields, &raw);
+            }
+
+            if let Some(point) = args.malformed_packet {
+                if info.malformed.is_none() && malformed_point(point, &connection_state, info) {
+                    let reaction =
+                        malformed(&mut client_reader, &mut client_writer, &mut decode_buf)
+                            .instrument(trace_span!("client.malformed"))
+                            .await?;
+                    debug!("Client reaction to malformed packet: {reaction:?}");
+
+                    let disconnected = reaction == Reaction::Disconnected;
+                    info.malformed = Some(reaction);
+                    if disconnected {
+                        record_info(info);
+                        return Ok(());
+                    }
+                }
+            }
+
+            if let State::InGame { .. } = connection_state {
+                if args.probe && info.probes.is_none() {
+                    info.probes = Some(
+                        probe(
+                            &mut client_reader,
+                            &mut client_writer,
+                            world,
+                            &mut decode_buf,
+                        )
+                        .instrument(trace_span!("client.probe"))
+                        .await?,
+                    );
+                }
+            }
+
+            if let State::Tarpit = connection_state {
+                let started = Instant::now();
+                let error = tarpit(&mut client_writer, world, args.tarpit_rate).await;
+
+                debug!(
+                    "Tarpit ended after {:?}, {} bytes sent: {error}",
+                    started.elapsed(),
+                    client_writer.traffic.sent.load(Ordering::Relaxed)
+                );
+                connection_state = State::Finished;
+            }
+
+            let finished = match &connection_state {
+                State::Finished => true,
+                State::InGame { until } => Instant::now() >= *until,
+                // when engaging keep going until the client's done with the world,
+                // otherwise just until we've got the interesting info
+                _ => {
+                    interest.engagement() == Engagement::None
+                        && info.name.is_some()
+                        && info.uuid.is_some()
+                }
+            };
+
+            if finished {
+                record_info(info);
+                return Ok(());
             }
         }
     }
 }
+
+/// Whether the connection's reached the point a malformed packet should be sent at.
+fn malformed_point(point: MalformedPoint, state: &State, info: &ClientInfo) -> bool {
+    match (point, state) {
+        (_, State::Finished) => false,
+        (MalformedPoint::Connect, State::ReceivingPassword | State::ReveivingInfo) => true,
+        (MalformedPoint::Info, _) => info.name.is_some() && info.uuid.is_some(),
+        (MalformedPoint::Spawn, State::InGame { .. }) => true,
+        _ => false,
+    }
+}
+
+fn record_info(info: &ClientInfo) {
+    let span = Span::current();
+    let recorder = Recorder::new(&span);
+    recorder
+        .record("version", &info.version)
+        .record("password", &info.password)
+        .record("player_name", &info.name)
+        .record("player_uuid", &info.uuid);
+
+    if info.tmodloader {
+        recorder.set_attribute("tmodloader", true);
+    }
+
+    if let Some(extension) = &info.connect_extension {
+        recorder.set_attribute("connect_request.extension", packet::hex(extension));
+    }
+
+    if let Some(flags) = &info.player_flags {
+        recorder
+            .set_attribute("player.difficulty", flags.difficulty)
+            .set_attribute("player.flags", flags.flags.join(","));
+
+        if let Some(extension) = &flags.extension {
+            recorder.set_attribute("player.extension", packet::hex(extension));
+        }
+    }
+
+    if let Some(mods) = &info.mods {
+        recorder.set_attribute("tmodloader.mods", mods.installed.join(","));
+
+        if let Some(requested) = &mods.requested {
+            recorder.set_attribute("tmodloader.requested_mod", requested.clone());
+        }
+    }
+
+    if let Some(reaction) = &info.malformed {
+        recorder.set_attribute("malformed.reaction", reaction.summary());
+    }
+
+    if let Some(probes) = &info.probes {
+        recorder.set_attribute("probe.responses", probes.summary());
+    }
+
+    if let Some(interest) = info.interest {
+        recorder.set_attribute("interest", interest as i64);
+    }
+
+    if let Some((x, y)) = info.requested_spawn {
+        recorder
+            .record("requested_spawn_x", x)
+            .record("requested_spawn_y", y);
+    }
+
+    if let Some(spawn) = &info.spawn {
+        recorder
+            .record("spawn_x", spawn.x)
+            .record("spawn_y", spawn.y)
+            .record("spawn_context", spawn.context);
+    }
+
+    if let Some(behavior) = &info.behavior {
+        behavior.record(&span);
+    }
+
+    if let Some(challenge) = &info.challenge {
+        challenge.record(&span);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(bytes: [u8; 3], extension: &[u8]) -> Bytes {
+        let mut body = BytesMut::new();
+        body.put_bytes(0, PlayerFlags::SKIPPED);
+        body.put_slice(&bytes);
+        body.put_slice(extension);
+        body.freeze()
+    }
+
+    #[test]
+    fn classic_without_flags() {
+        let flags = PlayerFlags::read(&mut body([0, 0, 0], b"")).unwrap();
+
+        assert_eq!(flags.difficulty, "classic");
+        assert!(flags.flags.is_empty());
+        assert_eq!(flags.extension, None);
+    }
+
+    #[test]
+    fn difficulty_and_flags() {
+        // journey & extra accessory, biome torches & super cart, aegis crystal & artisan bread
+        let flags = PlayerFlags::read(&mut body([0x0c, 0x11, 0x41], b"")).unwrap();
+
+        assert_eq!(flags.difficulty, "journey");
+        assert_eq!(
+            flags.flags,
+            [
+                "extra_accessory",
+                "biome_torches",
+                "enabled_super_cart",
+                "used_aegis_crystal",
+                "ate_artisan_bread"
+            ]
+        );
+
+        for (byte, difficulty) in [(0x01, "mediumcore"), (0x02, "hardcore")] {
+            let flags = PlayerFlags::read(&mut body([byte, 0, 0], b"")).unwrap();
+            assert_eq!(flags.difficulty, difficulty);
+        }
+    }
+
+    #[test]
+    fn unknown_bits_are_ignored() {
+        let flags = PlayerFlags::read(&mut body([0xf0, 0xe0, 0x80], b"")).unwrap();
+
+        assert_eq!(flags.difficulty, "classic");
+        assert!(flags.flags.is_empty());
+    }
+
+    #[test]
+    fn newer_clients_extension() {
+        let flags = PlayerFlags::read(&mut body([0, 0, 0], b"\x01\x02")).unwrap();
+
+        assert_eq!(flags.extension.as_deref(), Some(&b"\x01\x02"[..]));
+    }
+
+    #[test]
+    fn truncated_flags() {
+        let mut body = body([0, 0, 0], b"");
+        body.truncate(PlayerFlags::SKIPPED + 2);
+
+        assert!(matches!(
+            PlayerFlags::read(&mut body),
+            Err(SessionError::PacketTooShort)
+        ));
+        assert!(matches!(
+            PlayerFlags::read(&mut Bytes::new()),
+            Err(SessionError::PacketTooShort)
+        ));
+    }
+}
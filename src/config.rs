@@ -0,0 +1,159 @@
+use std::fmt::Write;
+
+use clap::{Arg, ArgAction, CommandFactory, Subcommand};
+use serde_json::{json, Map, Value};
+
+use crate::Args;
+
+// everything clap takes as a flag's value from the environment
+const BOOLISH: [&str; 12] = [
+    "true", "false", "yes", "no", "y", "n", "on", "off", "t", "f", "1", "0",
+];
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Print a JSON Schema for the environment.
+    ///
+    /// Every option as the environment variable that sets it, for validating env files
+    /// & templated deployments.
+    Schema,
+
+    /// Print an example env file.
+    ///
+    /// Every option as the environment variable that sets it, with its description & default.
+    Example,
+}
+
+pub fn run(command: &ConfigCommand) -> color_eyre::Result<()> {
+    let output = match command {
+        ConfigCommand::Schema => serde_json::to_string_pretty(&schema())? + "\n",
+        ConfigCommand::Example => example(),
+    };
+
+    print!("{output}");
+    Ok(())
+}
+
+/// Every option that can be set from the environment, straight from the argument definitions
+/// so it can't drift from what's actually parsed.
+fn options() -> Vec<Arg> {
+    Args::command()
+        .get_arguments()
+        .filter(|arg| arg.get_env().is_some())
+        .cloned()
+        .collect()
+}
+
+fn env(arg: &Arg) -> String {
+    arg.get_env()
+        .expect("only options with an env var are listed")
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn description(arg: &Arg) -> String {
+    arg.get_long_help()
+        .or(arg.get_help())
+        .map(ToString::to_string)
+        .unwrap_or_default()
+}
+
+fn defaults(arg: &Arg) -> Option<String> {
+    let defaults = arg
+        .get_default_values()
+        .iter()
+        .map(|value| value.to_string_lossy())
+        .collect::<Vec<_>>();
+
+    (!defaults.is_empty()).then(|| defaults.join(","))
+}
+
+/// Values the option accepts, none if it takes anything.
+fn choices(arg: &Arg) -> Option<Vec<String>> {
+    if matches!(arg.get_action(), ArgAction::SetTrue) {
+        return Some(vec!["true".to_owned(), "false".to_owned()]);
+    }
+
+    let choices = arg
+        .get_possible_values()
+        .iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_owned())
+        .collect::<Vec<_>>();
+
+    (!choices.is_empty()).then_some(choices)
+}
+
+fn schema() -> Value {
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+
+    for arg in options() {
+        let mut property = json!({
+            "type": "string",
+            "description": description(&arg),
+        });
+
+        if let Some(default) = defaults(&arg) {
+            property["default"] = default.into();
+        }
+
+        match (choices(&arg), arg.get_value_delimiter()) {
+            // a list of choices can't be an enum, so match it instead
+            (Some(choices), Some(delimiter)) => {
+                property["pattern"] = format!(
+                    "^({choices})({}({choices}))*$",
+                    regex::escape(&delimiter.to_string()),
+                    choices = choices.join("|"),
+                )
+                .into();
+            }
+            (Some(_), None) if matches!(arg.get_action(), ArgAction::SetTrue) => {
+                property["enum"] = BOOLISH.as_slice().into();
+            }
+            (Some(choices), None) => property["enum"] = choices.into(),
+            (None, _) => {}
+        }
+
+        if arg.is_required_set() {
+            required.push(env(&arg));
+        }
+
+        properties.insert(env(&arg), property);
+    }
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "bottled_honey environment",
+        "description": "Environment variables read by bottled_honey, every value is a string.",
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    })
+}
+
+fn example() -> String {
+    let mut example = String::from(
+        "# bottled_honey environment\n\
+        #\n\
+        # Generated by `bottled_honey config example`, uncomment anything to change it.\n",
+    );
+
+    for arg in options() {
+        example.push('\n');
+        for line in description(&arg).lines() {
+            let _ = writeln!(example, "#{}{line}", if line.is_empty() { "" } else { " " });
+        }
+
+        if let Some(choices) = choices(&arg) {
+            let _ = writeln!(example, "# (one of: {})", choices.join(", "));
+        }
+
+        // required options have to be set, so leave them uncommented
+        let comment = if arg.is_required_set() { "" } else { "# " };
+        let value = defaults(&arg).unwrap_or_default();
+        let _ = writeln!(example, "{comment}{}={value}", env(&arg));
+    }
+
+    example
+}
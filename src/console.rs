@@ -0,0 +1,281 @@
+use std::{
+    io,
+    net::{SocketAddr, SocketAddrV4},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use clap::Parser;
+use serde::Serialize;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{field, info, trace_span, warn, Instrument};
+
+use crate::{
+    attributes::Recorder,
+    error::{self, SessionError},
+    history::{History, Seen, Token},
+    sink::{SessionEvent, Sinks},
+};
+
+// people typing into a console are a lot slower than terraria clients
+const CONSOLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// after this many the console "locks out" the client
+const MAX_LOGINS: usize = 3;
+
+// no one's typing a username or password longer than this, telnet negotiation included
+const MAX_LINE_LENGTH: u64 = 1024;
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_secs(1);
+
+// telnet commands start with IAC, option negotiation takes one more byte
+// & subnegotiation runs until IAC SE
+const IAC: u8 = 0xff;
+const SB: u8 = 0xfa;
+const SE: u8 = 0xf0;
+
+#[derive(Debug, Parser)]
+pub struct ConsoleArgs {
+    /// Console decoy address.
+    ///
+    /// Also listen for telnet style remote console logins, scanners often check nearby
+    /// ports for an admin console after finding a server. Every login is refused.
+    /// (expected format: ip:port)
+    #[arg(
+        id = "console_address",
+        value_name = "ADDRESS",
+        env = "CONSOLE_ADDRESS",
+        long = "console-address"
+    )]
+    address: Option<SocketAddrV4>,
+}
+
+/// Username & password tried against the console.
+#[derive(Debug, Clone, Serialize)]
+pub struct Login {
+    pub username: String,
+    pub password: String,
+}
+
+pub async fn listen(
+    args: &ConsoleArgs,
+    history: Arc<History>,
+    sinks: Arc<Sinks>,
+) -> io::Result<Option<SocketAddr>> {
+    let Some(address) = args.address else {
+        return Ok(None);
+    };
+
+    let listener = TcpListener::bind(address).await?;
+    let address = listener.local_addr()?;
+    info!("Console decoy listening on {address}");
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer_addr) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(error) => {
+                    warn!("Failed to accept console connection: {error}");
+                    // errors like running out of file descriptors tend to stick around
+                    tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                    continue;
+                }
+            };
+
+            info!("New console connection from: {peer_addr:?}");
+            history.connected(peer_addr.ip());
+
+            let local_addr = match stream.local_addr() {
+                Ok(local_addr) => local_addr,
+                Err(error) => {
+                    warn!("Failed to get local address of console connection: {error}");
+                    continue;
+                }
+            };
+
+            let history = history.clone();
+            let sinks = sinks.clone();
+            let session_id = format!("{:016x}", fastrand::u64(..));
+
+            let span = trace_span!(
+                "console",
+                session_id = field::Empty,
+                peer_addr = field::Empty,
+                logins = field::Empty,
+                outcome = field::Empty,
+            );
+            Recorder::new(&span)
+                .record("session_id", field::display(&session_id))
+                .record("peer_addr", field::display(peer_addr));
+
+            tokio::spawn(
+                async move {
+                    let started = SystemTime::now();
+                    let mut logins = Vec::new();
+
+                    let result =
+                        handle_console(stream, peer_addr, &session_id, &history, &mut logins).await;
+                    Recorder::new(&tracing::Span::current())
+                        .record("logins", logins.len())
+                        .record("outcome", error::outcome(&result));
+
+                    match &result {
+                        Ok(()) => info!("Console client disconnected."),
+                        Err(error) => warn!("Console client unexpectedly disconnected: {error}"),
+                    }
+
+                    sinks.send(SessionEvent::console(
+                        &session_id,
+                        peer_addr,
+                        local_addr,
+                        started,
+                        logins,
+                        &result,
+                    ));
+                }
+                .instrument(span),
+            );
+        }
+    });
+
+    Ok(Some(address))
+}
+
+async fn handle_console(
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+    session_id: &str,
+    history: &History,
+    logins: &mut Vec<Login>,
+) -> Result<(), SessionError> {
+    let seen = Seen::current(peer_addr.ip(), session_id);
+    history.link_previous(&seen);
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    writer.write_all(b"Remote console\r\n\r\n").await?;
+
+    for _ in 0..MAX_LOGINS {
+        writer.write_all(b"login: ").await?;
+        let Some(username) = read_line(&mut reader).await? else {
+            return Ok(());
+        };
+
+        writer.write_all(b"Password: ").await?;
+        let Some(password) = read_line(&mut reader).await? else {
+            return Ok(());
+        };
+
+        info!(username, password, "Console login attempt");
+        history.observe(Token::Password, &password, &seen);
+        logins.push(Login { username, password });
+
+        // a real console would take a moment to check
+        tokio::time::sleep(Duration::from_millis(fastrand::u64(500..1500))).await;
+        writer.write_all(b"\r\nLogin incorrect\r\n").await?;
+    }
+
+    writer.write_all(b"Too many failed attempts\r\n").await?;
+    writer.shutdown().await?;
+
+    Ok(())
+}
+
+/// Read a line with any telnet commands taken out, none if the client's gone.
+async fn read_line(
+    reader: &mut (impl AsyncBufRead + Unpin),
+) -> Result<Option<String>, SessionError> {
+    let mut raw = Vec::new();
+    let read = tokio::time::timeout(
+        CONSOLE_TIMEOUT,
+        reader.take(MAX_LINE_LENGTH).read_until(b'\n', &mut raw),
+    )
+    .await??;
+
+    if read == 0 {
+        return Ok(None);
+    }
+    if !raw.ends_with(b"\n") && read as u64 == MAX_LINE_LENGTH {
+        return Err(SessionError::OversizedBuffer);
+    }
+
+    let mut line = Vec::new();
+    let mut bytes = raw.into_iter();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            IAC => match bytes.next() {
+                Some(SB) => {
+                    while let Some(byte) = bytes.next() {
+                        if byte == IAC && bytes.next() == Some(SE) {
+                            break;
+                        }
+                    }
+                }
+                // will, won't, do & don't
+                Some(0xfb..=0xfe) => {
+                    bytes.next();
+                }
+                _ => {}
+            },
+            b'\r' | b'\n' | b'\0' => {}
+            byte => line.push(byte),
+        }
+    }
+
+    Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read(mut raw: &[u8]) -> Result<Option<String>, SessionError> {
+        read_line(&mut raw).await
+    }
+
+    #[tokio::test]
+    async fn plain_line() {
+        assert_eq!(read(b"admin\r\n").await.unwrap().as_deref(), Some("admin"));
+        assert_eq!(read(b"admin\n").await.unwrap().as_deref(), Some("admin"));
+    }
+
+    #[tokio::test]
+    async fn telnet_commands_are_taken_out() {
+        // IAC WILL ECHO, IAC DO SUPPRESS-GO-AHEAD, then a window size subnegotiation
+        let raw = b"\xff\xfb\x01\xff\xfd\x03\xff\xfa\x1f\x00\x50\x00\x18\xff\xf0root\r\0\n";
+        assert_eq!(read(raw).await.unwrap().as_deref(), Some("root"));
+    }
+
+    #[tokio::test]
+    async fn truncated_commands_are_dropped() {
+        assert_eq!(read(b"root\xff").await.unwrap().as_deref(), Some("root"));
+        assert_eq!(
+            read(b"root\xff\xfa\x1f").await.unwrap().as_deref(),
+            Some("root")
+        );
+    }
+
+    #[tokio::test]
+    async fn disconnected_client() {
+        assert_eq!(read(b"").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn oversized_line() {
+        let raw = vec![b'a'; MAX_LINE_LENGTH as usize + 1];
+        assert!(matches!(
+            read(&raw).await,
+            Err(SessionError::OversizedBuffer)
+        ));
+
+        // a line of exactly the limit is fine as long as it ends there
+        let mut raw = vec![b'a'; MAX_LINE_LENGTH as usize - 1];
+        raw.push(b'\n');
+        assert_eq!(
+            read(&raw).await.unwrap().map(|line| line.len()),
+            Some(MAX_LINE_LENGTH as usize - 1)
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use color_eyre::eyre::{Context, Result};
+use serde_json::{json, Value};
+
+#[derive(Debug, Parser)]
+pub struct ExportArgs {
+    /// Output directory.
+    ///
+    /// Directory to write the dashboard & alert rules to.
+    #[arg(short, long, default_value = ".")]
+    output: PathBuf,
+}
+
+pub fn export(args: &ExportArgs) -> Result<()> {
+    std::fs::create_dir_all(&args.output).wrap_err("Failed to create output directory")?;
+
+    let files = [
+        (
+            "bottled_honey-dashboard.json",
+            serde_json::to_string_pretty(&dashboard())?,
+        ),
+        (
+            "bottled_honey-prometheus-rules.yaml",
+            PROMETHEUS_RULES.to_owned(),
+        ),
+        ("bottled_honey-loki-rules.yaml", LOKI_RULES.to_owned()),
+    ];
+
+    for (name, contents) in files {
+        let path = args.output.join(name);
+        std::fs::write(&path, contents)
+            .wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+
+        println!("Wrote {}", path.display());
+    }
+
+    Ok(())
+}
+
+/// Example Prometheus alert rules for the metrics sent over OTLP.
+///
+/// Metric names are as exported through the OpenTelemetry collector's Prometheus exporter,
+/// dots become underscores, counters get _total & units get added on.
+const PROMETHEUS_RULES: &str = r#"groups:
+  - name: bottled_honey
+    rules:
+      - alert: BottledHoneyEnrichmentFailing
+        expr: |
+          sum by (source) (rate(enrich_lookups_total{outcome="error"}[15m]))
+            / sum by (source) (rate(enrich_lookups_total{outcome=~"error|found|not_found"}[15m])) > 0.5
+        for: 15m
+        labels:
+          severity: warning
+        annotations:
+          summary: "Over half of {{ $labels.source }} enrichment lookups are failing"
+
+      - alert: BottledHoneyEnrichmentRateLimited
+        expr: sum by (source) (rate(enrich_lookups_total{outcome="rate_limited"}[15m])) > 0
+        for: 1h
+        labels:
+          severity: info
+        annotations:
+          summary: "{{ $labels.source }} enrichment has been hitting its rate limit for an hour"
+
+      - alert: BottledHoneySinkDroppingEvents
+        expr: sum by (sink, reason) (increase(sink_events_dropped_total[15m])) > 0
+        labels:
+          severity: critical
+        annotations:
+          summary: "{{ $labels.sink }} sink is dropping session events ({{ $labels.reason }})"
+
+      - alert: BottledHoneySinkBacklog
+        expr: sink_queue_depth > 1000
+        for: 15m
+        labels:
+          severity: warning
+        annotations:
+          summary: "{{ $labels.sink }} sink has had over a thousand events queued for 15 minutes"
+"#;
+
+/// Example Loki ruler alert rules for the session events sent by the Loki sink.
+const LOKI_RULES: &str = r#"groups:
+  - name: bottled_honey_sessions
+    rules:
+      - alert: BottledHoneySessionSpike
+        expr: |
+          sum(count_over_time({job="bottled_honey"}[10m]))
+            > 3 * sum(count_over_time({job="bottled_honey"}[1d] offset 10m)) / 144
+        for: 10m
+        labels:
+          severity: info
+        annotations:
+          summary: "Session rate is over three times the daily average"
+
+      - alert: BottledHoneySessionsStopped
+        expr: absent_over_time({job="bottled_honey"}[6h])
+        labels:
+          severity: warning
+        annotations:
+          summary: "No sessions have been recorded in six hours, is the honeypot still reachable?"
+"#;
+
+fn prometheus() -> Value {
+    json!({ "type": "prometheus", "uid": "${prometheus}" })
+}
+
+fn loki() -> Value {
+    json!({ "type": "loki", "uid": "${loki}" })
+}
+
+/// Panel at the given grid position, `targets` are queries against `datasource`.
+fn panel(
+    kind: &str,
+    title: &str,
+    (x, y, w, h): (u32, u32, u32, u32),
+    datasource: Value,
+    targets: &[(&str, &str)],
+) -> Value {
+    let targets = targets
+        .iter()
+        .enumerate()
+        .map(|(index, (expr, legend))| {
+            json!({
+                "refId": char::from(b'A' + index as u8).to_string(),
+                "datasource": datasource,
+                "expr": expr,
+                "legendFormat": legend,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "type": kind,
+        "title": title,
+        "gridPos": { "x": x, "y": y, "w": w, "h": h },
+        "datasource": datasource,
+        "targets": targets,
+    })
+}
+
+/// Grafana dashboard for the session events sent to Loki & the OTLP metrics.
+fn dashboard() -> Value {
+    // session fields are pulled out of the json lines rather than relying on
+    // --loki-session-labels, so the panels work whatever labels are set
+    let sessions = r#"{job="bottled_honey"} | json"#;
+
+    let panels = vec![
+        panel(
+            "timeseries",
+            "Sessions by outcome",
+            (0, 0, 12, 8),
+            loki(),
+            &[(
+                &format!("sum by (outcome) (count_over_time({sessions} [$__interval]))"),
+                "{{outcome}}",
+            )],
+        ),
+        panel(
+            "timeseries",
+            "Sessions by service",
+            (12, 0, 12, 8),
+            loki(),
+            &[(
+                &format!("sum by (service) (count_over_time({sessions} [$__interval]))"),
+                "{{service}}",
+            )],
+        ),
+        panel(
+            "table",
+            "Top passwords",
+            (0, 8, 8, 10),
+            loki(),
+            &[(
+                &format!(
+                    r#"topk(20, sum by (password) (count_over_time({sessions} | password != "" [$__range])))"#
+                ),
+                "{{password}}",
+            )],
+        ),
+        panel(
+            "table",
+            "Top addresses",
+            (8, 8, 8, 10),
+            loki(),
+            &[(
+                &format!("topk(20, sum by (peer_ip) (count_over_time({sessions} [$__range])))"),
+                "{{peer_ip}}",
+            )],
+        ),
+        panel(
+            "table",
+            "Client versions",
+            (16, 8, 8, 10),
+            loki(),
+            &[(
+                &format!(
+                    r#"topk(20, sum by (version) (count_over_time({sessions} | version != "" [$__range])))"#
+                ),
+                "{{version}}",
+            )],
+        ),
+        panel(
+            "timeseries",
+            "Enrichment lookups",
+            (0, 18, 8, 8),
+            prometheus(),
+            &[(
+                "sum by (source, outcome) (rate(enrich_lookups_total[$__rate_interval]))",
+                "{{source}} {{outcome}}",
+            )],
+        ),
+        panel(
+            "timeseries",
+            "Enrichment lookup duration (p95)",
+            (8, 18, 8, 8),
+            prometheus(),
+            &[(
+                "histogram_quantile(0.95, sum by (le) (rate(enrich_lookup_duration_seconds_bucket[$__rate_interval])))",
+                "p95",
+            )],
+        ),
+        panel(
+            "timeseries",
+            "Enrichment cache size",
+            (16, 18, 8, 8),
+            prometheus(),
+            &[("enrich_cache_size", "{{source}}")],
+        ),
+        panel(
+            "timeseries",
+            "Sink queue depth",
+            (0, 26, 8, 8),
+            prometheus(),
+            &[("sink_queue_depth", "{{sink}}")],
+        ),
+        panel(
+            "timeseries",
+            "Sink write duration (p95)",
+            (8, 26, 8, 8),
+            prometheus(),
+            &[(
+                "histogram_quantile(0.95, sum by (sink, le) (rate(sink_write_duration_seconds_bucket[$__rate_interval])))",
+                "{{sink}}",
+            )],
+        ),
+        panel(
+            "timeseries",
+            "Sink retries & dropped events",
+            (16, 26, 8, 8),
+            prometheus(),
+            &[
+                (
+                    "sum by (sink) (increase(sink_write_retries_total[$__rate_interval]))",
+                    "{{sink}} retries",
+                ),
+                (
+                    "sum by (sink, reason) (increase(sink_events_dropped_total[$__rate_interval]))",
+                    "{{sink}} dropped ({{reason}})",
+                ),
+            ],
+        ),
+        panel(
+            "logs",
+            "Sessions",
+            (0, 34, 24, 12),
+            loki(),
+            &[(r#"{job="bottled_honey"}"#, "")],
+        ),
+    ];
+
+    json!({
+        "title": "Bottled Honey",
+        "uid": "bottled-honey",
+        "tags": ["bottled_honey", "honeypot"],
+        "timezone": "utc",
+        "schemaVersion": 39,
+        "time": { "from": "now-24h", "to": "now" },
+        "templating": {
+            "list": [
+                { "name": "prometheus", "label": "Metrics", "type": "datasource", "query": "prometheus" },
+                { "name": "loki", "label": "Sessions", "type": "datasource", "query": "loki" },
+            ],
+        },
+        "panels": panels,
+    })
+}
@@ -0,0 +1,95 @@
+use std::{
+    fmt::Write as _,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tracing::info;
+
+use crate::{history::History, notify::Notifier};
+
+const TOP_FINGERPRINTS: usize = 5;
+const LISTED_CREDENTIALS: usize = 10;
+
+/// Send a digest of the last day to `notifier` every day at `hour` (UTC).
+pub async fn run(hour: u8, history: Arc<History>, notifier: Arc<Notifier>) {
+    // start off with an empty period rather than everything since startup
+    history.take_period();
+
+    loop {
+        tokio::time::sleep(until_hour(hour)).await;
+
+        let digest = summarise(&history);
+        info!("Sending daily digest.");
+        notifier.notify("Bottled Honey daily digest", &digest).await;
+    }
+}
+
+/// How long until the next time it's `hour` o'clock UTC.
+fn until_hour(hour: u8) -> Duration {
+    const DAY: u64 = 60 * 60 * 24;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let target = hour as u64 * 60 * 60;
+    let today = now % DAY;
+    let wait = (target + DAY - today) % DAY;
+
+    // exactly on the hour means the digest was just sent, wait for tomorrow's
+    Duration::from_secs(if wait == 0 { DAY } else { wait })
+}
+
+fn summarise(history: &History) -> String {
+    let period = history.take_period();
+    let mut digest = String::new();
+
+    let _ = writeln!(
+        digest,
+        "{} sessions from {} addresses ({} new, {} returning)",
+        period.sessions,
+        period.new_addresses.len() + period.returning_addresses.len(),
+        period.new_addresses.len(),
+        period.returning_addresses.len(),
+    );
+
+    if !period.fingerprints.is_empty() {
+        let mut fingerprints: Vec<_> = period.fingerprints.into_iter().collect();
+        fingerprints.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        let _ = writeln!(digest, "\nTop fingerprints:");
+        for (fingerprint, count) in fingerprints.iter().take(TOP_FINGERPRINTS) {
+            let _ = writeln!(digest, "  {count}x {fingerprint}");
+        }
+    }
+
+    if !period.new_credentials.is_empty() {
+        let listed: Vec<_> = period
+            .new_credentials
+            .iter()
+            .take(LISTED_CREDENTIALS)
+            .map(|password| format!("{password:?}"))
+            .collect();
+
+        let _ = write!(digest, "\nNew credentials: {}", listed.join(", "));
+        if period.new_credentials.len() > LISTED_CREDENTIALS {
+            let _ = write!(
+                digest,
+                " (+{} more)",
+                period.new_credentials.len() - LISTED_CREDENTIALS
+            );
+        }
+        let _ = writeln!(digest);
+    }
+
+    if !period.alerts.is_empty() {
+        let _ = writeln!(digest, "\nAlerts:");
+        for alert in &period.alerts {
+            let _ = writeln!(digest, "  {alert}");
+        }
+    }
+
+    digest
+}
@@ -0,0 +1,134 @@
+use std::{net::IpAddr, time::Duration};
+
+use clap::Parser;
+use serde::Deserialize;
+use tracing::{info, warn, Span};
+
+use super::{EnrichArgs, Lookups};
+use crate::attributes::Recorder;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Parser)]
+pub struct GreyNoiseArgs {
+    /// GreyNoise.
+    ///
+    /// Look up connecting addresses with GreyNoise and attach their classification.
+    #[arg(env = "GREYNOISE", long = "greynoise")]
+    enabled: bool,
+
+    /// GreyNoise API key.
+    ///
+    /// Key to use for GreyNoise lookups, the community API works without one.
+    #[arg(env = "GREYNOISE_KEY", long = "greynoise-key")]
+    key: Option<String>,
+
+    /// GreyNoise enterprise.
+    ///
+    /// Use the enterprise context API rather than the community API,
+    /// requires an api key.
+    #[arg(env = "GREYNOISE_ENTERPRISE", long = "greynoise-enterprise")]
+    enterprise: bool,
+
+    /// GreyNoise rate.
+    ///
+    /// Maximum number of lookups per minute,
+    /// anything over this isn't looked up.
+    #[arg(env = "GREYNOISE_RATE", long = "greynoise-rate", default_value_t = 10)]
+    rate: u32,
+}
+
+/// What GreyNoise knows about an address.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Classification {
+    /// benign, malicious or unknown
+    #[serde(default = "unknown")]
+    pub classification: String,
+    /// Name of the organisation the address belongs to, if it's a known scanner.
+    #[serde(alias = "actor")]
+    pub name: Option<String>,
+    #[serde(default, alias = "seen")]
+    pub noise: bool,
+    #[serde(default)]
+    pub riot: bool,
+}
+
+fn unknown() -> String {
+    "unknown".to_owned()
+}
+
+pub struct GreyNoise {
+    client: reqwest::Client,
+    key: Option<String>,
+    enterprise: bool,
+    lookups: Lookups<Classification>,
+}
+
+impl GreyNoise {
+    pub fn from_args(args: &GreyNoiseArgs, enrich: &EnrichArgs) -> Option<Self> {
+        if !args.enabled {
+            return None;
+        }
+
+        if args.enterprise && args.key.is_none() {
+            warn!("GreyNoise enterprise API requires an api key, lookups will probably fail.");
+        }
+
+        Some(Self {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("failed to build http client"),
+            key: args.key.clone(),
+            enterprise: args.enterprise,
+            lookups: Lookups::new("greynoise", args.rate, enrich),
+        })
+    }
+
+    /// Look up `ip` and record its classification onto the current span.
+    pub async fn enrich(&self, ip: IpAddr) -> Option<Classification> {
+        let classification = self.lookups.lookup(ip, || self.request(ip)).await?;
+
+        info!(
+            "GreyNoise classification: {} ({})",
+            classification.classification,
+            classification.name.as_deref().unwrap_or("no name")
+        );
+
+        let span = Span::current();
+        let recorder = Recorder::new(&span)
+            .set_attribute(
+                "greynoise.classification",
+                classification.classification.clone(),
+            )
+            .set_attribute("greynoise.noise", classification.noise)
+            .set_attribute("greynoise.riot", classification.riot);
+        if let Some(name) = &classification.name {
+            recorder.set_attribute("greynoise.name", name.clone());
+        }
+
+        Some(classification)
+    }
+
+    async fn request(&self, ip: IpAddr) -> reqwest::Result<Option<Classification>> {
+        let url = if self.enterprise {
+            format!("https://api.greynoise.io/v2/noise/context/{ip}")
+        } else {
+            format!("https://api.greynoise.io/v3/community/{ip}")
+        };
+
+        let mut request = self.client.get(url);
+        if let Some(key) = &self.key {
+            request = request.header("key", key);
+        }
+
+        let response = request.send().await?;
+
+        // the community api 404s for addresses it's never seen
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        response.error_for_status()?.json().await.map(Some)
+    }
+}
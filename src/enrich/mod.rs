@@ -0,0 +1,460 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    future::Future,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use opentelemetry::{
+    metrics::{Counter, Gauge, Histogram},
+    KeyValue,
+};
+use tokio::sync::Semaphore;
+use tracing::{debug, warn};
+
+mod greynoise;
+mod shodan;
+
+pub use greynoise::GreyNoiseArgs;
+pub use shodan::ShodanArgs;
+
+#[derive(Debug, Parser)]
+pub struct EnrichArgs {
+    /// Enrichment cache size.
+    ///
+    /// Maximum number of addresses each enricher keeps results for,
+    /// the least recently used are dropped first.
+    #[arg(
+        env = "ENRICH_CACHE_SIZE",
+        long = "enrich-cache-size",
+        default_value_t = 10_000
+    )]
+    cache_size: usize,
+
+    /// Enrichment cache ttl.
+    ///
+    /// How long a lookup result is kept before looking the address up again.
+    /// (in seconds)
+    #[arg(env = "ENRICH_CACHE_TTL", long = "enrich-cache-ttl", default_value_t = 60 * 60 * 24)]
+    cache_ttl: u64,
+
+    /// Enrichment negative cache ttl.
+    ///
+    /// How long to remember that there's nothing known about an address.
+    /// (in seconds)
+    #[arg(
+        env = "ENRICH_NEGATIVE_TTL",
+        long = "enrich-negative-ttl",
+        default_value_t = 60 * 60
+    )]
+    negative_ttl: u64,
+
+    /// Enrichment concurrency.
+    ///
+    /// Maximum number of lookups each enricher has in flight at once,
+    /// any more wait their turn.
+    #[arg(
+        env = "ENRICH_CONCURRENCY",
+        long = "enrich-concurrency",
+        default_value_t = 4
+    )]
+    concurrency: usize,
+}
+
+/// What every enricher found out about an address.
+#[derive(Debug, Default)]
+pub struct Enriched {
+    pub greynoise: Option<greynoise::Classification>,
+    pub shodan: Option<shodan::Host>,
+}
+
+/// Every enabled enricher, looked up alongside each session.
+pub struct Enrichment {
+    greynoise: Option<greynoise::GreyNoise>,
+    shodan: Option<shodan::Shodan>,
+}
+
+impl Enrichment {
+    pub fn from_args(args: &crate::Args) -> Self {
+        Self {
+            greynoise: greynoise::GreyNoise::from_args(&args.greynoise, &args.enrich),
+            shodan: shodan::Shodan::from_args(&args.shodan, &args.enrich),
+        }
+    }
+
+    /// Look up `ip` with every enricher and record the results onto the current span,
+    /// returns them for the session event.
+    pub async fn enrich(&self, ip: IpAddr) -> Enriched {
+        if !is_public(ip) {
+            return Enriched::default();
+        }
+
+        let greynoise = async {
+            match &self.greynoise {
+                Some(greynoise) => greynoise.enrich(ip).await,
+                None => None,
+            }
+        };
+
+        let shodan = async {
+            match &self.shodan {
+                Some(shodan) => shodan.enrich(ip).await,
+                None => None,
+            }
+        };
+
+        let (greynoise, shodan) = tokio::join!(greynoise, shodan);
+        Enriched { greynoise, shodan }
+    }
+}
+
+/// Cache, concurrency & rate limiting shared by all the enrichers.
+struct Lookups<V> {
+    source: &'static str,
+    cache: Mutex<Cache<V>>,
+    in_flight: Semaphore,
+    rate: u32,
+    /// Start of the current rate limit window & lookups made in it.
+    window: Mutex<(Instant, u32)>,
+    metrics: Metrics,
+}
+
+impl<V: Clone> Lookups<V> {
+    fn new(source: &'static str, rate: u32, args: &EnrichArgs) -> Self {
+        Self {
+            source,
+            cache: Mutex::new(Cache::new(
+                args.cache_size,
+                Duration::from_secs(args.cache_ttl),
+                Duration::from_secs(args.negative_ttl),
+            )),
+            in_flight: Semaphore::new(args.concurrency.max(1)),
+            rate,
+            window: Mutex::new((Instant::now(), 0)),
+            metrics: Metrics::new(),
+        }
+    }
+
+    /// Look up `ip`, going through the cache first.
+    ///
+    /// `request` should return `Ok(None)` when the source has nothing on the address,
+    /// that's cached for the (usually shorter) negative ttl.
+    async fn lookup<F, E>(&self, ip: IpAddr, request: impl FnOnce() -> F) -> Option<V>
+    where
+        F: Future<Output = Result<Option<V>, E>>,
+        E: Display,
+    {
+        if let Some(cached) = self.cached(ip) {
+            return cached;
+        }
+
+        let _permit = self
+            .in_flight
+            .acquire()
+            .await
+            .expect("lookup semaphore is never closed");
+
+        // someone else may have looked it up while waiting for a permit
+        if let Some(cached) = self.cached(ip) {
+            return cached;
+        }
+
+        if !self.take_rate_limit() {
+            debug!("{} rate limit reached, skipping lookup", self.source);
+            self.outcome("rate_limited");
+            return None;
+        }
+
+        let started = Instant::now();
+        let result = request().await;
+        self.metrics.duration.record(
+            started.elapsed().as_secs_f64(),
+            &[KeyValue::new("source", self.source)],
+        );
+
+        let value = match result {
+            Ok(value) => value,
+            Err(error) => {
+                warn!("{} lookup failed: {error}", self.source);
+                self.outcome("error");
+                return None;
+            }
+        };
+
+        self.outcome(if value.is_some() {
+            "found"
+        } else {
+            "not_found"
+        });
+
+        let mut cache = self.cache.lock().unwrap();
+        cache.insert(ip, value.clone());
+        self.metrics.cache_size.record(
+            cache.entries.len() as u64,
+            &[KeyValue::new("source", self.source)],
+        );
+
+        value
+    }
+
+    /// `Some` if `ip` is cached, with the cached value (`None` if negatively cached).
+    fn cached(&self, ip: IpAddr) -> Option<Option<V>> {
+        let cached = self.cache.lock().unwrap().get(ip);
+
+        match &cached {
+            Some(Some(_)) => self.outcome("hit"),
+            Some(None) => self.outcome("negative_hit"),
+            None => {}
+        }
+
+        cached
+    }
+
+    fn take_rate_limit(&self) -> bool {
+        let mut window = self.window.lock().unwrap();
+        if window.0.elapsed() >= Duration::from_secs(60) {
+            *window = (Instant::now(), 0);
+        }
+
+        if window.1 >= self.rate {
+            return false;
+        }
+
+        window.1 += 1;
+        true
+    }
+
+    fn outcome(&self, outcome: &'static str) {
+        self.metrics.lookups.add(
+            1,
+            &[
+                KeyValue::new("source", self.source),
+                KeyValue::new("outcome", outcome),
+            ],
+        );
+    }
+}
+
+/// TTL & LRU cache of lookup results, `None` being a negative result.
+struct Cache<V> {
+    entries: HashMap<IpAddr, CacheEntry<V>>,
+    /// Every use of an address, oldest first. Only an entry's latest use counts,
+    /// earlier ones are skipped over when evicting.
+    uses: VecDeque<(IpAddr, u64)>,
+    /// Number of the last use, so stale places in `uses` can be told apart.
+    last_use: u64,
+    capacity: usize,
+    ttl: Duration,
+    negative_ttl: Duration,
+}
+
+struct CacheEntry<V> {
+    value: Option<V>,
+    expires: Instant,
+    last_use: u64,
+}
+
+impl<V: Clone> Cache<V> {
+    fn new(capacity: usize, ttl: Duration, negative_ttl: Duration) -> Self {
+        Self {
+            entries: HashMap::new(),
+            uses: VecDeque::new(),
+            last_use: 0,
+            capacity,
+            ttl,
+            negative_ttl,
+        }
+    }
+
+    fn get(&mut self, ip: IpAddr) -> Option<Option<V>> {
+        let entry = self.entries.get(&ip)?;
+        if entry.expires <= Instant::now() {
+            self.entries.remove(&ip);
+            return None;
+        }
+
+        let value = entry.value.clone();
+        let last_use = self.used(ip);
+        if let Some(entry) = self.entries.get_mut(&ip) {
+            entry.last_use = last_use;
+        }
+
+        Some(value)
+    }
+
+    fn insert(&mut self, ip: IpAddr, value: Option<V>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&ip) {
+            self.evict();
+        }
+
+        let ttl = if value.is_some() {
+            self.ttl
+        } else {
+            self.negative_ttl
+        };
+
+        let last_use = self.used(ip);
+        self.entries.insert(
+            ip,
+            CacheEntry {
+                value,
+                expires: Instant::now() + ttl,
+                last_use,
+            },
+        );
+    }
+
+    /// Note `ip` was just used, returns the number of the use.
+    fn used(&mut self, ip: IpAddr) -> u64 {
+        // stale uses pile up with hits, clear them out once they outnumber the live ones
+        if self.uses.len() >= self.capacity.saturating_mul(2).max(16) {
+            let entries = &self.entries;
+            self.uses.retain(|(ip, last_use)| {
+                entries
+                    .get(ip)
+                    .is_some_and(|entry| entry.last_use == *last_use)
+            });
+        }
+
+        self.last_use += 1;
+        self.uses.push_back((ip, self.last_use));
+        self.last_use
+    }
+
+    /// Drop the least recently used entry.
+    fn evict(&mut self) {
+        while let Some((ip, last_use)) = self.uses.pop_front() {
+            if self
+                .entries
+                .get(&ip)
+                .is_some_and(|entry| entry.last_use == last_use)
+            {
+                self.entries.remove(&ip);
+                return;
+            }
+        }
+    }
+}
+
+struct Metrics {
+    lookups: Counter<u64>,
+    duration: Histogram<f64>,
+    cache_size: Gauge<u64>,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let meter = opentelemetry::global::meter("bottled_honey");
+
+        Self {
+            lookups: meter
+                .u64_counter("enrich.lookups")
+                .with_description("Enrichment lookups by source & outcome.")
+                .init(),
+            duration: meter
+                .f64_histogram("enrich.lookup.duration")
+                .with_description("Time taken by enrichment requests.")
+                .with_unit("s")
+                .init(),
+            cache_size: meter
+                .u64_gauge("enrich.cache.size")
+                .with_description("Addresses held in each enrichment cache.")
+                .init(),
+        }
+    }
+}
+
+/// Whether `ip` is worth looking up, private & local addresses never are.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation())
+        }
+        IpAddr::V6(ip) => {
+            let segment = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                // unique local
+                || (segment & 0xfe00) == 0xfc00
+                // link local
+                || (segment & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([192, 0, 2, last])
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = Cache::new(2, TTL, TTL);
+        cache.insert(ip(1), Some(1));
+        cache.insert(ip(2), Some(2));
+        // used more recently than 2 now
+        assert_eq!(cache.get(ip(1)), Some(Some(1)));
+
+        cache.insert(ip(3), Some(3));
+        assert_eq!(cache.get(ip(2)), None);
+        assert_eq!(cache.get(ip(1)), Some(Some(1)));
+        assert_eq!(cache.get(ip(3)), Some(Some(3)));
+    }
+
+    #[test]
+    fn stays_at_capacity_through_many_hits() {
+        let mut cache = Cache::new(3, TTL, TTL);
+        for last in 0..100 {
+            cache.insert(ip(last), Some(last));
+            for _ in 0..10 {
+                cache.get(ip(last));
+            }
+        }
+
+        assert_eq!(cache.entries.len(), 3);
+        assert!(cache.uses.len() <= 16);
+        assert_eq!(cache.get(ip(99)), Some(Some(99)));
+        assert_eq!(cache.get(ip(96)), None);
+    }
+
+    #[test]
+    fn keeps_negative_results() {
+        let mut cache = Cache::<u8>::new(1, TTL, TTL);
+        cache.insert(ip(1), None);
+
+        assert_eq!(cache.get(ip(1)), Some(None));
+    }
+
+    #[test]
+    fn drops_expired_entries() {
+        let mut cache = Cache::new(1, Duration::ZERO, TTL);
+        cache.insert(ip(1), Some(1));
+
+        assert_eq!(cache.get(ip(1)), None);
+    }
+
+    #[test]
+    fn zero_capacity_caches_nothing() {
+        let mut cache = Cache::new(0, TTL, TTL);
+        cache.insert(ip(1), Some(1));
+
+        assert_eq!(cache.get(ip(1)), None);
+    }
+}
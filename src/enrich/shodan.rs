@@ -0,0 +1,111 @@
+use std::{net::IpAddr, time::Duration};
+
+use clap::Parser;
+use opentelemetry::{Array, StringValue, Value};
+use serde::Deserialize;
+use tracing::{info, Span};
+
+use super::{EnrichArgs, Lookups};
+use crate::attributes::Recorder;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Parser)]
+pub struct ShodanArgs {
+    /// Shodan InternetDB.
+    ///
+    /// Look up connecting addresses in Shodan's InternetDB and attach
+    /// their known open ports, tags & CPEs.
+    #[arg(id = "shodan", env = "SHODAN", long = "shodan")]
+    enabled: bool,
+
+    /// Shodan InternetDB rate.
+    ///
+    /// Maximum number of lookups per minute,
+    /// anything over this isn't looked up.
+    #[arg(
+        id = "shodan_rate",
+        value_name = "RATE",
+        env = "SHODAN_RATE",
+        long = "shodan-rate",
+        default_value_t = 60
+    )]
+    rate: u32,
+}
+
+/// What Shodan's seen running on an address.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Host {
+    pub ports: Vec<u16>,
+    pub tags: Vec<String>,
+    pub cpes: Vec<String>,
+    pub vulns: Vec<String>,
+    pub hostnames: Vec<String>,
+}
+
+pub struct Shodan {
+    client: reqwest::Client,
+    lookups: Lookups<Host>,
+}
+
+impl Shodan {
+    pub fn from_args(args: &ShodanArgs, enrich: &EnrichArgs) -> Option<Self> {
+        if !args.enabled {
+            return None;
+        }
+
+        Some(Self {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("failed to build http client"),
+            lookups: Lookups::new("shodan", args.rate, enrich),
+        })
+    }
+
+    /// Look up `ip` and record what's known about it onto the current span.
+    pub async fn enrich(&self, ip: IpAddr) -> Option<Host> {
+        let host = self.lookups.lookup(ip, || self.request(ip)).await?;
+
+        info!(
+            "Shodan InternetDB: ports {:?}, tags {:?}",
+            host.ports, host.tags
+        );
+
+        fn strings(values: &[String]) -> Value {
+            Value::Array(Array::String(
+                values.iter().cloned().map(StringValue::from).collect(),
+            ))
+        }
+
+        Recorder::new(&Span::current())
+            .set_attribute(
+                "shodan.ports",
+                Value::Array(Array::I64(
+                    host.ports.iter().copied().map(i64::from).collect(),
+                )),
+            )
+            .set_attribute("shodan.tags", strings(&host.tags))
+            .set_attribute("shodan.cpes", strings(&host.cpes))
+            .set_attribute("shodan.vulns", strings(&host.vulns))
+            .set_attribute("shodan.hostnames", strings(&host.hostnames));
+
+        Some(host)
+    }
+
+    async fn request(&self, ip: IpAddr) -> reqwest::Result<Option<Host>> {
+        let response = self
+            .client
+            .get(format!("https://internetdb.shodan.io/{ip}"))
+            .send()
+            .await?;
+
+        // addresses shodan's never seen 404
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        response.error_for_status()?.json().await.map(Some)
+    }
+}
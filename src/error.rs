@@ -0,0 +1,86 @@
+use std::{fmt, io};
+
+/// Why a session ended early.
+#[derive(Debug)]
+pub enum SessionError {
+    /// Client went quiet for longer than it was given.
+    Timeout,
+    /// Too much was received without it making up a packet.
+    OversizedBuffer,
+    /// Packet header gave a length shorter than the header itself.
+    InvalidLength,
+    /// Packet body ended before everything expected was read.
+    PacketTooShort,
+    /// ConnectRequest wasn't from anything claiming to be Terraria.
+    UnknownSignature,
+    /// Client disconnected or reset the connection.
+    PeerClosed,
+    /// Anything else the connection threw up.
+    Io(io::Error),
+}
+
+impl SessionError {
+    /// Stable name for the outcome of a session that ended with this error,
+    /// for aggregating on in sinks & traces.
+    pub fn outcome(&self) -> &'static str {
+        match self {
+            SessionError::Timeout => "timeout",
+            SessionError::OversizedBuffer => "oversized_buffer",
+            SessionError::InvalidLength => "invalid_length",
+            SessionError::PacketTooShort => "packet_too_short",
+            SessionError::UnknownSignature => "unknown_signature",
+            SessionError::PeerClosed => "peer_closed",
+            SessionError::Io(_) => "io_error",
+        }
+    }
+}
+
+/// Outcome of a finished session, "completed" if it didn't end with an error.
+pub fn outcome(result: &Result<(), SessionError>) -> &'static str {
+    match result {
+        Ok(()) => "completed",
+        Err(error) => error.outcome(),
+    }
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::Timeout => write!(f, "Timed out"),
+            SessionError::OversizedBuffer => write!(f, "Buffer too large"),
+            SessionError::InvalidLength => write!(f, "Invalid packet length"),
+            SessionError::PacketTooShort => write!(f, "Packet body too short"),
+            SessionError::UnknownSignature => write!(f, "Unknown signature"),
+            SessionError::PeerClosed => write!(f, "Connection closed by peer"),
+            SessionError::Io(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SessionError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SessionError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::TimedOut => SessionError::Timeout,
+            io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe => SessionError::PeerClosed,
+            _ => SessionError::Io(error),
+        }
+    }
+}
+
+impl From<tokio::time::error::Elapsed> for SessionError {
+    fn from(_: tokio::time::error::Elapsed) -> Self {
+        SessionError::Timeout
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    fmt::Write,
+    io,
+    net::{SocketAddr, SocketAddrV4},
+    sync::Arc,
+    time::Duration,
+};
+
+use clap::Parser;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, info, warn};
+
+use crate::sink::Sinks;
+
+// long enough for anything polling it, short enough that nothing can hang onto a connection
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Parser)]
+pub struct HealthArgs {
+    /// Health check address.
+    ///
+    /// Serve a plain HTTP health check, it responds with 503 while any sink has
+    /// been failing for longer than the sink failure threshold.
+    /// (expected format: ip:port)
+    #[arg(
+        id = "health_address",
+        value_name = "ADDRESS",
+        env = "HEALTH_ADDRESS",
+        long = "health-address"
+    )]
+    address: Option<SocketAddrV4>,
+
+    /// Health check sink failure threshold.
+    ///
+    /// How long a sink can go without a successful write before the health check fails.
+    /// (in seconds)
+    #[arg(
+        env = "HEALTH_SINK_THRESHOLD",
+        long = "health-sink-threshold",
+        default_value_t = 300
+    )]
+    sink_threshold: u64,
+}
+
+pub async fn listen(args: &HealthArgs, sinks: Arc<Sinks>) -> io::Result<Option<SocketAddr>> {
+    let Some(address) = args.address else {
+        return Ok(None);
+    };
+
+    let listener = TcpListener::bind(address).await?;
+    let address = listener.local_addr()?;
+    info!("Health check listening on {address}");
+
+    let threshold = Duration::from_secs(args.sink_threshold);
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(error) => {
+                    warn!("Failed to accept health check connection: {error}");
+                    // errors like running out of file descriptors tend to stick around
+                    tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                    continue;
+                }
+            };
+
+            let sinks = sinks.clone();
+            tokio::spawn(async move {
+                if let Err(error) = respond(stream, &sinks, threshold).await {
+                    debug!("Failed to respond to health check: {error}");
+                }
+            });
+        }
+    });
+
+    Ok(Some(address))
+}
+
+/// Answer whatever was asked with the current health, there's only the one thing to check.
+async fn respond(mut stream: TcpStream, sinks: &Sinks, threshold: Duration) -> io::Result<()> {
+    // the request itself doesn't matter, just wait for it before responding
+    let mut request = [0; 1024];
+    tokio::time::timeout(REQUEST_TIMEOUT, stream.read(&mut request)).await??;
+
+    let failing = sinks.failing(threshold);
+    let (status, body) = if failing.is_empty() {
+        ("200 OK", "ok\n".to_owned())
+    } else {
+        let mut body = String::new();
+        for (sink, failing_for) in &failing {
+            let _ = writeln!(body, "{sink} sink failing for {}s", failing_for.as_secs());
+        }
+
+        ("503 Service Unavailable", body)
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\n\
+        Content-Type: text/plain\r\n\
+        Content-Length: {}\r\n\
+        Connection: close\r\n\r\n\
+        {body}",
+        body.len()
+    );
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
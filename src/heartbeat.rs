@@ -0,0 +1,128 @@
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use clap::Parser;
+use serde_json::json;
+use tracing::{debug, warn};
+
+use crate::{history::History, sink::Sinks};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Parser)]
+pub struct HeartbeatArgs {
+    /// Heartbeat url.
+    ///
+    /// Post a heartbeat here every heartbeat interval, with what's been seen since the last
+    /// one, so a fleet dashboard can tell a quiet sensor from a dead one.
+    #[arg(
+        id = "heartbeat_url",
+        value_name = "URL",
+        env = "HEARTBEAT_URL",
+        long = "heartbeat-url",
+        requires = "heartbeat_node_id"
+    )]
+    url: Option<String>,
+
+    /// Heartbeat node id.
+    ///
+    /// Name this sensor goes by in heartbeats, required with a heartbeat url.
+    #[arg(
+        id = "heartbeat_node_id",
+        value_name = "NODE_ID",
+        env = "HEARTBEAT_NODE_ID",
+        long = "heartbeat-node-id"
+    )]
+    node_id: Option<String>,
+
+    /// Heartbeat interval.
+    ///
+    /// How often to post a heartbeat.
+    /// (in seconds)
+    #[arg(
+        id = "heartbeat_interval",
+        value_name = "INTERVAL",
+        env = "HEARTBEAT_INTERVAL",
+        long = "heartbeat-interval",
+        default_value_t = 60,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    interval: u64,
+}
+
+/// Post a heartbeat every interval until shutdown, starting straight away
+/// so new sensors show up without waiting.
+///
+/// `listeners` are every service that's listening, with the address it's bound to.
+pub async fn run(
+    args: &HeartbeatArgs,
+    labels: &[(String, String)],
+    history: Arc<History>,
+    sinks: Arc<Sinks>,
+    listeners: Vec<(&'static str, SocketAddr)>,
+) {
+    let (Some(url), Some(node_id)) = (args.url.clone(), args.node_id.clone()) else {
+        return;
+    };
+
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .expect("failed to build http client");
+
+    let interval = Duration::from_secs(args.interval);
+    let started = SystemTime::now();
+    let uptime = Instant::now();
+
+    // only count from startup
+    history.take_beat();
+
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+
+        let beat = history.take_beat();
+        let failing = sinks.failing(Duration::ZERO);
+
+        let body = json!({
+            "node_id": node_id,
+            "version": env!("CARGO_PKG_VERSION"),
+            "labels": labels.iter().cloned().collect::<BTreeMap<_, _>>(),
+            "started": humantime::format_rfc3339_seconds(started).to_string(),
+            "uptime": uptime.elapsed().as_secs(),
+            // so the receiver knows how long to wait before calling the sensor dead
+            "interval": interval.as_secs(),
+            "listeners": listeners
+                .iter()
+                .map(|(name, address)| json!({ "name": name, "address": address.to_string() }))
+                .collect::<Vec<_>>(),
+            "failing_sinks": failing
+                .iter()
+                .map(|(sink, failing_for)| json!({ "sink": sink, "failing_for": failing_for.as_secs() }))
+                .collect::<Vec<_>>(),
+            "since_last": {
+                "sessions": beat.sessions,
+                "addresses": beat.addresses.len(),
+                "alerts": beat.alerts,
+            },
+        });
+
+        let result = client
+            .post(&url)
+            .json(&body)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status);
+
+        // the counts from a missed beat are lost, the next beat only covers its own interval
+        match result {
+            Ok(_) => debug!("Sent heartbeat."),
+            Err(error) => warn!("Failed to send heartbeat: {error}"),
+        }
+    }
+}
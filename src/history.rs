@@ -0,0 +1,457 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    hash::Hash,
+    net::IpAddr,
+    sync::Mutex,
+};
+
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt};
+use tracing::{error, Span};
+
+use crate::attributes::Recorder;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+// enough to cover a good while of sessions without growing forever
+const HISTORY_SIZE: usize = 100_000;
+
+// plenty for a digest, anything past these is just counted
+const PERIOD_FINGERPRINTS: usize = 10_000;
+const PERIOD_CREDENTIALS: usize = 1_000;
+const PERIOD_ALERTS: usize = 100;
+
+// an identity turning up from this many addresses is probably one actor behind proxies
+const IDENTITY_ALERT_ADDRESSES: usize = 4;
+// past this it's clearly shared, no point tracking every address
+const MAX_KNOWN_ADDRESSES: usize = 256;
+
+// enough to walk back through an attacker's history a session at a time
+// without a single trace collecting hundreds of links
+const PREVIOUS_SESSIONS: usize = 10;
+
+/// Values worth keeping track of across sessions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Token {
+    Password,
+    Uuid,
+    PlayerName,
+    /// Code handed out by a chat code challenge.
+    ChallengeCode,
+}
+
+impl Token {
+    fn name(self) -> &'static str {
+        match self {
+            Token::Password => "password",
+            Token::Uuid => "uuid",
+            Token::PlayerName => "player_name",
+            Token::ChallengeCode => "challenge_code",
+        }
+    }
+}
+
+/// The session a value was seen in.
+pub struct Seen {
+    pub ip: IpAddr,
+    pub session_id: String,
+    /// Span of the session, should be the session's `client` span.
+    pub span: Span,
+    /// Spans already linked to, so the same one isn't linked twice.
+    linked: Mutex<HashSet<SpanId>>,
+}
+
+impl Seen {
+    pub fn current(ip: IpAddr, session_id: &str) -> Self {
+        Self {
+            ip,
+            session_id: session_id.to_owned(),
+            span: Span::current(),
+            linked: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn context(&self) -> SpanContext {
+        self.span.context().span().span_context().clone()
+    }
+
+    fn link(&self, span: &SpanContext) {
+        if self.linked.lock().unwrap().insert(span.span_id()) {
+            self.span.add_link(span.clone());
+        }
+    }
+
+    /// Link to each of `sessions` & record their ids as `attribute`.
+    fn link_sessions<'a>(
+        &self,
+        attribute: &'static str,
+        sessions: impl IntoIterator<Item = &'a PreviousSession>,
+    ) {
+        let mut ids = Vec::new();
+        for session in sessions {
+            self.link(&session.span);
+            ids.push(session.session_id.as_str());
+        }
+
+        if !ids.is_empty() {
+            Recorder::new(&self.span).set_attribute(attribute, ids.join(","));
+        }
+    }
+}
+
+/// An earlier session, kept around to link back to.
+struct PreviousSession {
+    session_id: String,
+    span: SpanContext,
+}
+
+impl PreviousSession {
+    fn from_seen(seen: &Seen) -> Self {
+        Self {
+            session_id: seen.session_id.clone(),
+            span: seen.context(),
+        }
+    }
+}
+
+/// Remember `seen` in `sessions`, dropping the oldest once there's too many.
+fn push_session(sessions: &mut VecDeque<PreviousSession>, seen: &Seen) {
+    if sessions.len() >= PREVIOUS_SESSIONS {
+        sessions.pop_front();
+    }
+    sessions.push_back(PreviousSession::from_seen(seen));
+}
+
+struct Sighting {
+    ip: IpAddr,
+    session_id: String,
+    span: SpanContext,
+    /// Only alert on the first replay, dictionary passwords would be a firehose otherwise.
+    alerted: bool,
+    /// Every address the value's come from, including the first.
+    addresses: HashSet<IpAddr>,
+    /// Most recent sessions the value was seen in, oldest first.
+    sessions: VecDeque<PreviousSession>,
+}
+
+/// Map of up to `HISTORY_SIZE` values, along with the order they were first inserted in
+/// so the oldest can be dropped without looking through all of them.
+struct Bounded<K, V> {
+    values: HashMap<K, V>,
+    /// Oldest first.
+    order: VecDeque<K>,
+}
+
+impl<K, V> Default for Bounded<K, V> {
+    fn default() -> Self {
+        Self {
+            values: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V> Bounded<K, V> {
+    fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.values.get_mut(key)
+    }
+
+    /// Insert `value` if `key` isn't already there, dropping the oldest if it's full.
+    /// Returns whether it was inserted.
+    fn insert(&mut self, key: K, value: V) -> bool {
+        if self.values.contains_key(&key) {
+            return false;
+        }
+
+        if self.values.len() >= HISTORY_SIZE {
+            if let Some(oldest) = self.order.pop_front() {
+                self.values.remove(&oldest);
+            }
+        }
+
+        self.order.push_back(key.clone());
+        self.values.insert(key, value);
+        true
+    }
+
+    /// Value for `key`, inserting the default if it isn't there.
+    fn get_or_default(&mut self, key: K) -> &mut V
+    where
+        V: Default,
+    {
+        self.insert(key.clone(), V::default());
+        self.values.entry(key).or_default()
+    }
+}
+
+/// What's happened since the last time the period was taken, for digests.
+#[derive(Debug, Default)]
+pub struct Period {
+    pub sessions: u32,
+    pub new_addresses: HashSet<IpAddr>,
+    pub returning_addresses: HashSet<IpAddr>,
+    /// Client version & player name, with how many sessions used them.
+    pub fingerprints: HashMap<String, u32>,
+    /// Passwords never seen before this period.
+    pub new_credentials: Vec<String>,
+    pub alerts: Vec<String>,
+}
+
+/// What's happened since the last heartbeat.
+#[derive(Debug, Default)]
+pub struct Beat {
+    pub sessions: u32,
+    pub addresses: HashSet<IpAddr>,
+    pub alerts: u32,
+}
+
+/// Connections since the rates were last taken, for anomaly detection.
+#[derive(Debug, Default)]
+pub struct Rates {
+    pub connections: u64,
+    /// Connections that got as far as a ConnectRequest.
+    pub handshakes: u64,
+}
+
+/// Values seen in earlier sessions & where they were first seen,
+/// so values turning up from somewhere else can be spotted.
+#[derive(Default)]
+pub struct History {
+    sightings: Mutex<Bounded<(Token, String), Sighting>>,
+    addresses: Mutex<Bounded<IpAddr, ()>>,
+    /// Client version & player name of earlier sessions, kept apart from the period
+    /// so rare fingerprints are still spotted without a digest taking it.
+    fingerprints: Mutex<Bounded<String, ()>>,
+    /// Most recent sessions from each address, oldest first.
+    previous: Mutex<Bounded<IpAddr, VecDeque<PreviousSession>>>,
+    period: Mutex<Period>,
+    beat: Mutex<Beat>,
+    rates: Mutex<Rates>,
+}
+
+impl History {
+    /// Note a new session from `ip`.
+    pub fn connected(&self, ip: IpAddr) {
+        self.rates.lock().unwrap().connections += 1;
+
+        let returning = !self.addresses.lock().unwrap().insert(ip, ());
+
+        let mut beat = self.beat.lock().unwrap();
+        beat.sessions += 1;
+        if beat.addresses.len() < HISTORY_SIZE {
+            beat.addresses.insert(ip);
+        }
+
+        let mut period = self.period.lock().unwrap();
+        period.sessions += 1;
+        // the period's only taken by the digest, so without one it'd grow forever
+        if period.new_addresses.len() + period.returning_addresses.len() >= HISTORY_SIZE {
+            return;
+        }
+        // first seen this period still counts as new
+        if returning && !period.new_addresses.contains(&ip) {
+            period.returning_addresses.insert(ip);
+        } else if !returning {
+            period.new_addresses.insert(ip);
+        }
+    }
+
+    /// Note a session sent a ConnectRequest, so it's probably a client rather than a port scan.
+    pub fn handshake(&self) {
+        self.rates.lock().unwrap().handshakes += 1;
+    }
+
+    /// Link the session to earlier ones from the same address,
+    /// then remember it for the next.
+    pub fn link_previous(&self, seen: &Seen) {
+        let mut previous = self.previous.lock().unwrap();
+        let sessions = previous.get_or_default(seen.ip);
+        seen.link_sessions("previous_session_ids", sessions.iter());
+        push_session(sessions, seen);
+    }
+
+    /// Note the client version & player name a session used,
+    /// returns whether it's the first session to use it.
+    pub fn fingerprint(&self, fingerprint: String) -> bool {
+        let first = self
+            .fingerprints
+            .lock()
+            .unwrap()
+            .insert(fingerprint.clone(), ());
+
+        let mut period = self.period.lock().unwrap();
+        if let Some(count) = period.fingerprints.get_mut(&fingerprint) {
+            *count += 1;
+        } else if period.fingerprints.len() < PERIOD_FINGERPRINTS {
+            period.fingerprints.insert(fingerprint, 1);
+        }
+
+        first
+    }
+
+    /// Everything since the period was last taken, starting a new one.
+    pub fn take_period(&self) -> Period {
+        std::mem::take(&mut self.period.lock().unwrap())
+    }
+
+    /// Everything since the last heartbeat, starting a new beat.
+    pub fn take_beat(&self) -> Beat {
+        std::mem::take(&mut self.beat.lock().unwrap())
+    }
+
+    /// Connections since the rates were last taken, starting over.
+    pub fn take_rates(&self) -> Rates {
+        std::mem::take(&mut self.rates.lock().unwrap())
+    }
+
+    /// Check `value` hasn't come from another address, then remember it.
+    /// Returns how many addresses it's come from.
+    pub fn observe(&self, token: Token, value: &str, seen: &Seen) -> usize {
+        self.check(token, value, seen);
+        self.remember(token, value, seen)
+    }
+
+    /// Alert if `value` was first seen from another address.
+    pub fn check(&self, token: Token, value: &str, seen: &Seen) {
+        if value.is_empty() {
+            return;
+        }
+
+        let mut sightings = self.sightings.lock().unwrap();
+        let Some(first) = sightings.get_mut(&(token, value.to_owned())) else {
+            return;
+        };
+
+        if first.ip == seen.ip || first.alerted {
+            return;
+        }
+        first.alerted = true;
+
+        self.beat.lock().unwrap().alerts += 1;
+
+        let mut period = self.period.lock().unwrap();
+        if period.alerts.len() < PERIOD_ALERTS {
+            period.alerts.push(format!(
+                "{} {value:?} from {} replayed from {}",
+                token.name(),
+                first.ip,
+                seen.ip
+            ));
+        }
+
+        error!(
+            alert = "cross_ip_reuse",
+            token = token.name(),
+            value,
+            first_session_id = %first.session_id,
+            first_peer_ip = %first.ip,
+            peer_ip = %seen.ip,
+            session_id = %seen.session_id,
+            "Reused {} first seen from {} (session {}) replayed from {} (session {})",
+            token.name(),
+            first.ip,
+            first.session_id,
+            seen.ip,
+            seen.session_id,
+        );
+
+        seen.link(&first.span);
+    }
+
+    /// Remember `value` as seen in this session, or the address it came from if it's been
+    /// seen before. Returns how many addresses it's come from.
+    pub fn remember(&self, token: Token, value: &str, seen: &Seen) -> usize {
+        if value.is_empty() {
+            return 0;
+        }
+
+        let mut sightings = self.sightings.lock().unwrap();
+        let key = (token, value.to_owned());
+        if let Some(sighting) = sightings.get_mut(&key) {
+            if sighting.addresses.len() < MAX_KNOWN_ADDRESSES
+                && sighting.addresses.insert(seen.ip)
+                && sighting.addresses.len() == IDENTITY_ALERT_ADDRESSES
+                && matches!(token, Token::Uuid | Token::PlayerName)
+            {
+                self.many_addresses(token, value, sighting.addresses.len(), seen);
+            }
+
+            // one uuid is one player, so its other sessions are worth being able to walk through
+            if token == Token::Uuid
+                && sighting
+                    .sessions
+                    .back()
+                    .is_some_and(|last| last.session_id != seen.session_id)
+            {
+                seen.link_sessions("uuid.previous_session_ids", sighting.sessions.iter());
+                push_session(&mut sighting.sessions, seen);
+            }
+
+            return sighting.addresses.len();
+        }
+
+        if token == Token::Password {
+            let mut period = self.period.lock().unwrap();
+            if period.new_credentials.len() < PERIOD_CREDENTIALS {
+                period.new_credentials.push(value.to_owned());
+            }
+        }
+
+        sightings.insert(
+            key,
+            Sighting {
+                ip: seen.ip,
+                session_id: seen.session_id.clone(),
+                span: seen.context(),
+                alerted: false,
+                addresses: HashSet::from([seen.ip]),
+                sessions: VecDeque::from([PreviousSession::from_seen(seen)]),
+            },
+        );
+
+        1
+    }
+
+    /// Alert on an identity that's now come from a suspicious number of addresses.
+    fn many_addresses(&self, token: Token, value: &str, addresses: usize, seen: &Seen) {
+        self.beat.lock().unwrap().alerts += 1;
+
+        let mut period = self.period.lock().unwrap();
+        if period.alerts.len() < PERIOD_ALERTS {
+            period.alerts.push(format!(
+                "{} {value:?} seen from {addresses} addresses",
+                token.name()
+            ));
+        }
+
+        error!(
+            alert = "identity_many_addresses",
+            token = token.name(),
+            value,
+            known_ips = addresses,
+            peer_ip = %seen.ip,
+            session_id = %seen.session_id,
+            "{} {value:?} has now been seen from {addresses} addresses, latest {} (session {})",
+            token.name(),
+            seen.ip,
+            seen.session_id,
+        );
+    }
+
+    /// Alert on `rate` of `metric` (connections or handshakes) this hour being way off
+    /// the `baseline` for the hour.
+    pub fn rate_anomaly(&self, metric: &str, rate: u64, baseline: f64, message: &str) {
+        self.beat.lock().unwrap().alerts += 1;
+
+        let mut period = self.period.lock().unwrap();
+        if period.alerts.len() < PERIOD_ALERTS {
+            period.alerts.push(message.to_owned());
+        }
+
+        error!(
+            alert = "rate_anomaly",
+            metric,
+            rate,
+            baseline = baseline.round() as u64,
+            "{message}",
+        );
+    }
+}
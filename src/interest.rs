@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+use clap::Parser;
+use tracing::info;
+
+use crate::{schedule::Persona, Engagement};
+
+#[derive(Debug, Parser)]
+pub struct InterestArgs {
+    /// Interest threshold.
+    ///
+    /// Score a session needs to reach before it's treated as interesting, interesting
+    /// sessions get the interest engagement & timeout instead of the usual ones.
+    /// Every session is treated the same if unset.
+    #[arg(env = "INTEREST_THRESHOLD", long = "interest-threshold")]
+    threshold: Option<u32>,
+
+    /// Interest engagement.
+    ///
+    /// How far to play along with interesting sessions,
+    /// never less than the usual engagement.
+    #[arg(
+        id = "interest_engagement",
+        value_name = "ENGAGEMENT",
+        env = "INTEREST_ENGAGEMENT",
+        long = "interest-engagement",
+        value_enum,
+        default_value_t = Engagement::Spawn
+    )]
+    engagement: Engagement,
+
+    /// Interest timeout.
+    ///
+    /// How long to wait on interesting clients before giving up on them.
+    /// (in seconds)
+    #[arg(
+        env = "INTEREST_TIMEOUT",
+        long = "interest-timeout",
+        default_value_t = 30
+    )]
+    timeout: u64,
+
+    /// Interest password score.
+    ///
+    /// Added to a session's score when the client sends a password.
+    #[arg(
+        env = "INTEREST_PASSWORD_SCORE",
+        long = "interest-password-score",
+        default_value_t = 10
+    )]
+    password_score: u32,
+
+    /// Interest fingerprint score.
+    ///
+    /// Added to a session's score when its client version & player name
+    /// haven't been seen yet this digest period.
+    #[arg(
+        env = "INTEREST_FINGERPRINT_SCORE",
+        long = "interest-fingerprint-score",
+        default_value_t = 10
+    )]
+    fingerprint_score: u32,
+}
+
+impl InterestArgs {
+    /// Deepest engagement a session could end up at,
+    /// so anything decided up front can allow for it.
+    pub fn deepest(&self, persona: &Persona) -> Engagement {
+        if self.threshold.is_some() && !persona.shedding {
+            persona.engagement.max(self.engagement)
+        } else {
+            persona.engagement
+        }
+    }
+}
+
+/// Something a client did that makes it worth a closer look.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    Password,
+    /// Version & player name combination not seen yet this period.
+    RareFingerprint,
+}
+
+/// Running score of how interesting a session is.
+pub struct Interest<'a> {
+    args: &'a InterestArgs,
+    /// Persona the session started with.
+    base: &'a Persona,
+    score: u32,
+}
+
+impl<'a> Interest<'a> {
+    pub fn new(args: &'a InterestArgs, base: &'a Persona) -> Self {
+        Self {
+            args,
+            base,
+            score: 0,
+        }
+    }
+
+    pub fn add(&mut self, signal: Signal) {
+        let was_interesting = self.interesting();
+        self.score += match signal {
+            Signal::Password => self.args.password_score,
+            Signal::RareFingerprint => self.args.fingerprint_score,
+        };
+
+        if !was_interesting && self.interesting() {
+            info!(
+                "Session scored {} interest after {signal:?}, engaging as {:?}.",
+                self.score,
+                self.engagement()
+            );
+        }
+    }
+
+    /// Score so far, none if scoring's turned off.
+    pub fn score(&self) -> Option<u32> {
+        self.args.threshold.map(|_| self.score)
+    }
+
+    /// Whether the session gets the interest engagement & timeout,
+    /// never while shedding load since they'd only make sessions longer.
+    fn interesting(&self) -> bool {
+        !self.base.shedding
+            && self
+                .args
+                .threshold
+                .is_some_and(|threshold| self.score >= threshold)
+    }
+
+    pub fn engagement(&self) -> Engagement {
+        if self.interesting() {
+            self.base.engagement.max(self.args.engagement)
+        } else {
+            self.base.engagement
+        }
+    }
+
+    /// How long to wait on the client between packets.
+    pub fn idle_timeout(&self) -> Duration {
+        if self.interesting() {
+            Duration::from_secs(self.args.timeout)
+        } else {
+            self.base.idle_timeout
+        }
+    }
+}
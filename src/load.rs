@@ -0,0 +1,218 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use opentelemetry::metrics::Gauge;
+use tracing::{info, warn};
+
+// accept rate is measured over this window
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Parser)]
+pub struct LoadArgs {
+    /// Load session threshold.
+    ///
+    /// Start shedding load once this many sessions are running at once. While shedding,
+    /// timeouts are tightened & sessions aren't engaged with, so each one is over quickly.
+    #[arg(env = "LOAD_MAX_SESSIONS", long = "load-max-sessions")]
+    max_sessions: Option<usize>,
+
+    /// Load accept rate threshold.
+    ///
+    /// Start shedding load once connections come in faster than this.
+    /// (in connections per second)
+    #[arg(env = "LOAD_MAX_ACCEPT_RATE", long = "load-max-accept-rate")]
+    max_accept_rate: Option<usize>,
+
+    /// Load shedding idle timeout.
+    ///
+    /// How long to wait on clients between packets while shedding load.
+    /// (in milliseconds)
+    #[arg(
+        env = "LOAD_SHEDDING_IDLE_TIMEOUT",
+        long = "load-shedding-idle-timeout",
+        default_value_t = 1000
+    )]
+    shedding_idle_timeout: u64,
+
+    /// Load shedding password timeout.
+    ///
+    /// How long to wait on clients to send a password while shedding load.
+    /// (in seconds)
+    #[arg(
+        env = "LOAD_SHEDDING_PASSWORD_TIMEOUT",
+        long = "load-shedding-password-timeout",
+        default_value_t = 5
+    )]
+    shedding_password_timeout: u64,
+
+    /// Load recovery time.
+    ///
+    /// How long load has to stay under every threshold before going back to normal,
+    /// so a wave that comes & goes doesn't flip back & forth.
+    /// (in seconds)
+    #[arg(
+        env = "LOAD_RECOVERY_TIME",
+        long = "load-recovery-time",
+        default_value_t = 60
+    )]
+    recovery_time: u64,
+}
+
+impl LoadArgs {
+    pub fn shedding_idle_timeout(&self) -> Duration {
+        Duration::from_millis(self.shedding_idle_timeout)
+    }
+
+    pub fn shedding_password_timeout(&self) -> Duration {
+        Duration::from_secs(self.shedding_password_timeout)
+    }
+}
+
+/// Keeps track of how busy the honeypot is & whether it should be shedding load.
+pub struct Load {
+    max_sessions: Option<usize>,
+    max_accept_rate: Option<usize>,
+    recovery_time: Duration,
+    active: Arc<AtomicUsize>,
+    /// Recent accepts, oldest first, never more than the rate threshold's worth.
+    accepts: Mutex<VecDeque<Instant>>,
+    /// When load was last over a threshold, none if it's not shedding.
+    shedding_since: Mutex<Option<Instant>>,
+    mode: Gauge<u64>,
+    sessions: Gauge<u64>,
+}
+
+impl Load {
+    pub fn from_args(args: &LoadArgs) -> Self {
+        let meter = opentelemetry::global::meter("bottled_honey");
+
+        let load = Self {
+            max_sessions: args.max_sessions,
+            max_accept_rate: args.max_accept_rate,
+            recovery_time: Duration::from_secs(args.recovery_time),
+            active: Arc::default(),
+            accepts: Mutex::default(),
+            shedding_since: Mutex::default(),
+            mode: meter
+                .u64_gauge("load.shedding")
+                .with_description("1 while shedding load, 0 otherwise.")
+                .init(),
+            sessions: meter
+                .u64_gauge("load.sessions")
+                .with_description("Sessions running at once.")
+                .init(),
+        };
+
+        load.mode.record(0, &[]);
+        load
+    }
+
+    /// Keep checking load even while no one's connecting,
+    /// so it goes back to normal once the wave's passed.
+    pub async fn watch(self: Arc<Self>) {
+        if self.max_sessions.is_none() && self.max_accept_rate.is_none() {
+            return;
+        }
+
+        let mut ticks = tokio::time::interval(RATE_WINDOW);
+        loop {
+            ticks.tick().await;
+
+            let active = self.active.load(Ordering::Relaxed);
+            let rate = self.rate(Instant::now(), false);
+            self.sessions.record(active as u64, &[]);
+            self.update(self.over(active, rate), active, rate);
+        }
+    }
+
+    /// Note a new connection, returns whether load's being shed along with a guard
+    /// that counts the session as running until it's dropped.
+    pub fn accepted(&self) -> (bool, SessionGuard) {
+        let active = self.active.fetch_add(1, Ordering::Relaxed) + 1;
+        let guard = SessionGuard {
+            active: self.active.clone(),
+        };
+
+        let rate = self.rate(Instant::now(), true);
+        self.sessions.record(active as u64, &[]);
+        (self.update(self.over(active, rate), active, rate), guard)
+    }
+
+    /// Connections accepted in the last second, counting one accepted `now` if `accepted`.
+    /// None if there's no rate threshold.
+    fn rate(&self, now: Instant, accepted: bool) -> Option<usize> {
+        let max = self.max_accept_rate?;
+
+        let mut accepts = self.accepts.lock().unwrap();
+        while accepts
+            .front()
+            .is_some_and(|accept| now.duration_since(*accept) >= RATE_WINDOW)
+        {
+            accepts.pop_front();
+        }
+
+        if accepted {
+            // only ever needs to know if it's gone past the threshold
+            if accepts.len() > max {
+                accepts.pop_front();
+            }
+            accepts.push_back(now);
+        }
+
+        Some(accepts.len())
+    }
+
+    fn over(&self, active: usize, rate: Option<usize>) -> bool {
+        self.max_sessions.is_some_and(|max| active > max)
+            || self
+                .max_accept_rate
+                .zip(rate)
+                .is_some_and(|(max, rate)| rate > max)
+    }
+
+    /// Move between modes, returns whether load's being shed.
+    fn update(&self, over: bool, active: usize, rate: Option<usize>) -> bool {
+        let mut shedding_since = self.shedding_since.lock().unwrap();
+
+        match (*shedding_since, over) {
+            (None, true) => {
+                warn!(
+                    "Shedding load, {active} sessions running{}.",
+                    rate.map(|rate| format!(" & {rate} accepted in the last second"))
+                        .unwrap_or_default()
+                );
+                self.mode.record(1, &[]);
+            }
+            (Some(since), false) if since.elapsed() >= self.recovery_time => {
+                info!("Load back under thresholds, no longer shedding load.");
+                self.mode.record(0, &[]);
+                *shedding_since = None;
+                return false;
+            }
+            (None, false) => return false,
+            (Some(_), false) => return true,
+            (Some(_), true) => {}
+        }
+
+        *shedding_since = Some(Instant::now());
+        true
+    }
+}
+
+/// Counts a session as running until dropped.
+pub struct SessionGuard {
+    active: Arc<AtomicUsize>,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -1,13 +1,49 @@
-use std::{net::SocketAddrV4, str::FromStr, time::Duration};
+use std::{
+    net::SocketAddrV4,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
-use clap::Parser;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use color_eyre::eyre::{Context, Result};
 use opentelemetry_otlp::WithExportConfig;
 use tokio::net::TcpListener;
 use tracing::{field, info, trace_span, warn, Instrument};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+use world::{WorldPool, WorldSelection};
 
+mod anomaly;
+mod attributes;
+mod behavior;
+mod challenge;
+mod chat;
 mod client;
+mod config;
+mod console;
+mod dashboards;
+mod digest;
+mod enrich;
+mod error;
+mod health;
+mod heartbeat;
+mod history;
+mod interest;
+mod load;
+mod mdns;
+mod modloader;
+mod net;
+mod notify;
+mod packet;
+mod port_mapping;
+mod probe;
+mod sampling;
+mod schedule;
+mod sink;
+mod snmp;
+mod transcript;
+mod world;
 
 // don't spend all day waiting for peers to respond
 // may need tuning
@@ -18,7 +54,7 @@ pub(crate) const IDLE_TIMEOUT: Duration = Duration::from_secs(3);
 pub(crate) const MAX_BUFFER_LENGTH: usize = 1024 * 5;
 
 #[derive(Debug, Parser)]
-#[command(about, version)]
+#[command(about, version, subcommand_negates_reqs = true)]
 /// A very basic Terraria honeypot.
 ///
 /// A Terraria honeypot, it will listen for connection requests,
@@ -29,8 +65,8 @@ struct Args {
     ///
     /// The address the honeypot should bind to.
     /// (expected format: ip:port)
-    #[arg(env)]
-    address: SocketAddrV4,
+    #[arg(env, required = true)]
+    address: Option<SocketAddrV4>,
 
     /// Password chance.
     ///
@@ -39,8 +75,276 @@ struct Args {
     #[arg(env, short = 'p', default_value_t = 0.0)]
     password_chance: f32,
 
+    /// Engagement.
+    ///
+    /// How far the honeypot should play along with clients once they've connected.
+    #[arg(env, short = 'e', long, value_enum, default_value_t = Engagement::None)]
+    engagement: Engagement,
+
+    /// World names.
+    ///
+    /// Names of the fake worlds sent to clients when engaging,
+    /// one is picked for each connection.
+    /// (expects the format of "name,name", each up to 1024 bytes)
+    #[arg(env, long, value_delimiter = ',', value_parser = packet::parse_string)]
+    world_names: Vec<String>,
+
+    /// World names file.
+    ///
+    /// File with more world names to pick from, one per line.
+    /// (each up to 1024 bytes)
+    #[arg(env, long)]
+    world_names_file: Option<PathBuf>,
+
+    /// World selection.
+    ///
+    /// How the world is picked for each connection.
+    #[arg(env, long, value_enum, default_value_t = WorldSelection::PerIp)]
+    world_selection: WorldSelection,
+
+    /// Observation window.
+    ///
+    /// How long to keep watching a client's movement, projectiles and chat
+    /// once it's spawned in, only applies when engaging.
+    /// (in seconds, 0 to disconnect straight away)
+    #[arg(env, long, default_value_t = 0)]
+    observe_window: u64,
+
+    /// Tarpit rate.
+    ///
+    /// How fast junk world data should be sent to clients when tarpitting.
+    /// (in bytes per second)
+    #[arg(env, long, default_value_t = 2048)]
+    tarpit_rate: u32,
+
+    /// Message of the day.
+    ///
+    /// Chat message sent to clients once they've spawned in.
+    /// (up to 1024 bytes)
+    #[arg(env, long, value_parser = packet::parse_string)]
+    motd: Option<String>,
+
+    /// Chat responses.
+    ///
+    /// Canned responses sent to clients when a chat message they send matches a pattern,
+    /// only the first matching pattern is used.
+    /// (expects the format of "regex=>response" with the response up to 1024 bytes,
+    /// can be passed multiple times)
+    #[arg(env, long = "chat-response", value_parser = chat::parse_chat_response)]
+    chat_responses: Vec<chat::ChatResponse>,
+
+    /// Verification challenge.
+    ///
+    /// Fake verification request sent once a client has spawned in,
+    /// only applies when observing.
+    #[arg(env, long, value_enum)]
+    challenge: Option<challenge::ChallengeKind>,
+
+    /// Capability probing.
+    ///
+    /// Once a client has spawned in, send it a few unusual but legal packets one at a time
+    /// & record which packets it sends back to each, only applies when observing.
+    #[arg(env, long)]
+    probe: bool,
+
+    /// Malformed packet.
+    ///
+    /// Research option, send a single subtly malformed packet at this point in the connection
+    /// & record whether the client disconnects, ignores it or answers.
+    #[arg(env, long, value_enum)]
+    malformed_packet: Option<probe::MalformedPoint>,
+
+    /// Fake mod list.
+    ///
+    /// Mods to tell tModLoader clients the server has, so modded bots go through mod syncing
+    /// & carry on connecting. Clients asking to download one are kicked, finishing without
+    /// downloading any is them claiming to have every mod installed.
+    /// (expects the format of "name@version,name@version", up to 200 mods with names
+    /// & versions up to 128 bytes)
+    #[arg(env, long, value_delimiter = ',', value_parser = modloader::parse_fake_mod)]
+    fake_mods: Vec<modloader::FakeMod>,
+
+    /// Transcript directory.
+    ///
+    /// Directory to write a full transcript of every packet sent & received to,
+    /// one file per session named by its session id.
+    #[arg(env, long)]
+    transcript_dir: Option<PathBuf>,
+
+    /// Raw packet events.
+    ///
+    /// Attach every packet sent & received to the trace as a hex encoded span event,
+    /// cut down to this many bytes. Off by default as it's a lot of data & may hold
+    /// anything the client sent.
+    /// (in bytes)
+    #[arg(env, long)]
+    raw_packet_events: Option<usize>,
+
+    /// Schedule.
+    ///
+    /// Hours of the day where the honeypot acts differently, so it looks less
+    /// like an always on sensor. Later windows win when they overlap, windows that wrap
+    /// past midnight belong to the day they start on.
+    /// (expects the format of "[days ]start-end=change" in hours of the schedule offset,
+    /// where days are like "mon-fri" or "sat,sun" & change is one of "full",
+    /// "password:chance" or "engagement:level", can be passed multiple times)
+    #[arg(env, long, value_parser = schedule::parse_schedule_window)]
+    schedule: Vec<schedule::ScheduleWindow>,
+
+    /// Schedule offset.
+    ///
+    /// Offset from UTC the schedule's hours & days are in. Fixed, so it has to be changed
+    /// for daylight saving.
+    /// (expects the format of "+hh:mm" or "-hh:mm")
+    #[arg(
+        env,
+        long,
+        default_value = "+00:00",
+        allow_hyphen_values = true,
+        value_parser = schedule::parse_offset
+    )]
+    schedule_offset: i64,
+
+    /// Labels.
+    ///
+    /// Static labels attached to every span, metric & session event, like the site, tenant
+    /// or region the honeypot's running for, so data from many honeypots can be routed
+    /// & filtered downstream.
+    /// (expects the format of "key=val,key=val", can be passed multiple times)
+    #[arg(env, long = "label", value_delimiter = ',', value_parser = parse_label)]
+    labels: Vec<(String, String)>,
+
+    /// Digest hour.
+    ///
+    /// Hour of the day to send a digest of the last 24 hours to the notification webhooks,
+    /// no digest is sent if unset.
+    /// (in UTC, from 0 to 23)
+    #[arg(env, long, value_parser = clap::value_parser!(u8).range(0..24))]
+    digest_hour: Option<u8>,
+
+    #[group(flatten)]
+    anomaly: anomaly::AnomalyArgs,
+
+    #[group(flatten)]
+    interest: interest::InterestArgs,
+
+    #[group(flatten)]
+    load: load::LoadArgs,
+
+    #[group(flatten)]
+    console: console::ConsoleArgs,
+
+    #[group(flatten)]
+    port_mapping: port_mapping::PortMappingArgs,
+
+    #[group(flatten)]
+    mdns: mdns::MdnsArgs,
+
+    #[group(flatten)]
+    health: health::HealthArgs,
+
+    #[group(flatten)]
+    heartbeat: heartbeat::HeartbeatArgs,
+
+    #[group(flatten)]
+    notify: notify::NotifyArgs,
+
+    #[group(flatten)]
+    snmp: snmp::SnmpArgs,
+
+    #[group(flatten)]
+    clickhouse: sink::ClickHouseArgs,
+
+    #[group(flatten)]
+    loki: sink::LokiArgs,
+
+    #[group(flatten)]
+    eventlog: sink::EventLogArgs,
+
+    #[group(flatten)]
+    ipfix: sink::IpfixArgs,
+
+    #[group(flatten)]
+    zabbix: sink::ZabbixArgs,
+
+    #[group(flatten)]
+    enrich: enrich::EnrichArgs,
+
+    #[group(flatten)]
+    greynoise: enrich::GreyNoiseArgs,
+
+    #[group(flatten)]
+    shodan: enrich::ShodanArgs,
+
+    #[group(flatten)]
+    attributes: attributes::AttributeArgs,
+
     #[group(flatten)]
     opentelemetry: OpenTelemetryArgs,
+
+    #[group(flatten)]
+    sentry: SentryArgs,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+impl Args {
+    fn address(&self) -> SocketAddrV4 {
+        self.address
+            .expect("address is required unless running a subcommand")
+    }
+}
+
+/// Parse a label in the format of "key=val".
+fn parse_label(value: &str) -> Result<(String, String), String> {
+    value
+        .split_once('=')
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .ok_or_else(|| "expected the format of \"key=val\"".to_owned())
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Export Grafana dashboards.
+    ///
+    /// Write a Grafana dashboard & example alert rules matching the metrics & session
+    /// events the honeypot sends, ready to import.
+    ExportDashboards(dashboards::ExportArgs),
+
+    /// Generate configuration files.
+    ///
+    /// Print a schema or example of every option, generated from the options themselves.
+    Config {
+        #[command(subcommand)]
+        command: config::ConfigCommand,
+    },
+
+    /// Generate shell completions.
+    ///
+    /// Print a completion script for the given shell.
+    /// (e.g. for bash: source <(bottled_honey completions bash))
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+
+    /// Generate a man page.
+    ///
+    /// Print a roff man page for packagers to install as bottled_honey.1.
+    #[command(hide = true)]
+    Mangen,
+}
+
+// ordered from least to most engaged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Engagement {
+    /// Disconnect as soon as the player's info has been received.
+    None,
+    /// Send just enough of the world for the client to spawn in.
+    Spawn,
+    /// Advertise a huge world then very slowly send junk tile data.
+    Tarpit,
 }
 
 #[derive(Debug, Parser)]
@@ -51,23 +355,180 @@ struct OpenTelemetryArgs {
     #[arg(env = "OTEL_ENDPOINT", long = "otel-endpoint")]
     endpoint: Option<String>,
 
+    /// OpenTelemetry metrics endpoint.
+    ///
+    /// The opentelemetry endpoint to send metrics to,
+    /// defaults to the traces endpoint with /v1/traces swapped for /v1/metrics.
+    #[arg(env = "OTEL_METRICS_ENDPOINT", long = "otel-metrics-endpoint")]
+    metrics_endpoint: Option<String>,
+
     /// OpenTelemetry headers.
     ///
     /// Extra headers to be sent to the opentelemetry endpoint.
     /// (expects the format of "key=val,key=val")
     #[arg(env = "OTEL_HEADERS", long = "otel-headers")]
     headers: Option<String>,
+
+    /// OpenTelemetry sample first.
+    ///
+    /// Always export the first this many sessions from each address every hour,
+    /// then only sample the rest. Every session is exported if unset.
+    #[arg(env = "OTEL_SAMPLE_FIRST", long = "otel-sample-first")]
+    sample_first: Option<u32>,
+
+    /// OpenTelemetry sample ratio.
+    ///
+    /// Portion of sessions past the first few from an address that are still exported.
+    /// (from 0.0 to 1.0)
+    #[arg(
+        env = "OTEL_SAMPLE_RATIO",
+        long = "otel-sample-ratio",
+        default_value_t = 0.1
+    )]
+    sample_ratio: f64,
+}
+
+#[derive(Debug, Parser)]
+struct SentryArgs {
+    /// Sentry DSN.
+    ///
+    /// Report internal errors & panics to Sentry,
+    /// anything caused by clients misbehaving isn't reported.
+    #[arg(env = "SENTRY_DSN", long = "sentry-dsn")]
+    dsn: Option<String>,
+
+    /// Sentry environment.
+    #[arg(env = "SENTRY_ENVIRONMENT", long = "sentry-environment")]
+    environment: Option<String>,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = setup()?;
+    color_eyre::install()?;
+
+    let args = Args::parse();
+    // each mod's checked as it's parsed, but only the whole list can be too long
+    if args.fake_mods.len() > modloader::MAX_FAKE_MODS {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::TooManyValues,
+                format!(
+                    "too many fake mods, {} when the most is {}",
+                    args.fake_mods.len(),
+                    modloader::MAX_FAKE_MODS
+                ),
+            )
+            .exit();
+    }
+
+    if let Some(command) = &args.command {
+        return match command {
+            Command::ExportDashboards(export) => dashboards::export(export),
+            Command::Config { command } => config::run(command),
+            Command::Completions { shell } => {
+                let mut command = Args::command();
+                let name = command.get_name().to_owned();
+                clap_complete::generate(*shell, &mut command, name, &mut std::io::stdout());
+
+                Ok(())
+            }
+            Command::Mangen => {
+                clap_mangen::Man::new(Args::command()).render(&mut std::io::stdout())?;
+
+                Ok(())
+            }
+        };
+    }
+
+    let (args, _sentry, port_mapping) = setup(args).await?;
+    let port_mapping = port_mapping.map(Arc::new);
+
+    // however serving ends the mapping's removed, rather than left open until the lease runs out
+    let result = serve(Arc::new(args), port_mapping.clone()).await;
+
+    if let Some(port_mapping) = &port_mapping {
+        port_mapping.remove().await;
+    }
+
+    result
+}
+
+async fn serve(
+    args: Arc<Args>,
+    port_mapping: Option<Arc<port_mapping::PortMapping>>,
+) -> Result<()> {
+    let worlds = Arc::new(WorldPool::from_args(&args)?);
+    let enrichment = Arc::new(enrich::Enrichment::from_args(&args));
+    let history = Arc::new(history::History::default());
+    let sinks = Arc::new(sink::Sinks::from_args(&args)?);
+    let sampler = args
+        .opentelemetry
+        .sample_first
+        .map(|first| sampling::IpSampler::new(first, args.opentelemetry.sample_ratio));
+    let notifier = notify::Notifier::from_args(&args.notify).map(Arc::new);
+    let mdns = mdns::Mdns::start(&args.mdns, args.address()).await?;
+    let load = Arc::new(load::Load::from_args(&args.load));
+    tokio::spawn(load.clone().watch());
+
+    if let Some(hour) = args.digest_hour {
+        match &notifier {
+            Some(notifier) => {
+                tokio::spawn(digest::run(hour, history.clone(), notifier.clone()));
+            }
+            None => {
+                warn!("Digest hour set without any notification webhooks, no digest will be sent.")
+            }
+        }
+    }
+
+    tokio::spawn({
+        let args = args.clone();
+        let history = history.clone();
+        let notifier = notifier.clone();
+        async move { anomaly::run(&args.anomaly, history, notifier).await }
+    });
+
+    if let Some(directory) = &args.transcript_dir {
+        std::fs::create_dir_all(directory).wrap_err("Failed to create transcript directory")?;
+    }
+
+    let console_address = console::listen(&args.console, history.clone(), sinks.clone())
+        .await
+        .wrap_err("Failed to bind console decoy address")?;
 
-    let listener = TcpListener::bind(args.address)
+    let health_address = health::listen(&args.health, sinks.clone())
+        .await
+        .wrap_err("Failed to bind health check address")?;
+
+    let listener = TcpListener::bind(args.address())
         .await
         .wrap_err("Failed to bind to address")?;
 
-    info!("Server listening on {}", listener.local_addr()?);
+    let address = listener.local_addr()?;
+    info!("Server listening on {address}");
+
+    tokio::spawn({
+        let listeners = [
+            ("terraria", Some(address)),
+            ("console", console_address),
+            ("health", health_address),
+        ]
+        .into_iter()
+        .filter_map(|(name, address)| Some((name, address?)))
+        .collect();
+
+        let args = args.clone();
+        let history = history.clone();
+        let sinks = sinks.clone();
+        async move { heartbeat::run(&args.heartbeat, &args.labels, history, sinks, listeners).await }
+    });
+
+    if let Some(port_mapping) = &port_mapping {
+        tokio::spawn({
+            let port_mapping = port_mapping.clone();
+            async move { port_mapping.keep_alive().await }
+        });
+    }
 
     loop {
         tokio::select! {
@@ -81,29 +542,113 @@ async fn main() -> Result<()> {
                 stream
                     .set_nodelay(true)
                     .wrap_err("Failed to set nodelay on peer")?;
+                let local_addr = stream
+                    .local_addr()
+                    .wrap_err("Failed to get local address of peer")?;
 
                 info!("New connection from: {peer_addr:?}");
+                history.connected(peer_addr.ip());
+
+                let args = args.clone();
+                let enrichment = enrichment.clone();
+                let history = history.clone();
+                let sinks = sinks.clone();
+                let (shedding, load_guard) = load.accepted();
+                let persona = schedule::Persona::current(&args, shedding);
+                let world = worlds.pick(peer_addr.ip(), args.interest.deepest(&persona));
+                let session_id = format!("{:016x}", fastrand::u64(..));
+
+                let span = trace_span!(
+                    "client",
+                    session_id = field::Empty,
+                    peer_addr = field::Empty,
+                    world_name = field::Empty,
+                    bytes_sent = field::Empty,
+                    version = field::Empty,
+                    password = field::Empty,
+                    player_name = field::Empty,
+                    player_uuid = field::Empty,
+                    requested_spawn_x = field::Empty,
+                    requested_spawn_y = field::Empty,
+                    spawn_x = field::Empty,
+                    spawn_y = field::Empty,
+                    spawn_context = field::Empty,
+                    behavior.duration = field::Empty,
+                    behavior.packets = field::Empty,
+                    behavior.packet_rate = field::Empty,
+                    behavior.movement_rate = field::Empty,
+                    behavior.distance = field::Empty,
+                    behavior.projectile_rate = field::Empty,
+                    behavior.projectile_types = field::Empty,
+                    behavior.chat_messages = field::Empty,
+                    behavior.chat = field::Empty,
+                    challenge.kind = field::Empty,
+                    challenge.code = field::Empty,
+                    challenge.response = field::Empty,
+                    challenge.response_time = field::Empty,
+                    challenge.passed = field::Empty,
+                    outcome = field::Empty
+                );
+                attributes::Recorder::new(&span)
+                    .record("session_id", field::display(&session_id))
+                    .record("peer_addr", field::display(peer_addr))
+                    .record("world_name", field::display(&world.name));
+                if mdns.as_ref().is_some_and(|mdns| mdns.queried(peer_addr.ip())) {
+                    info!("{} looked the server up over mDNS before connecting", peer_addr.ip());
+                    attributes::Recorder::new(&span).set_attribute("mdns.queried", true);
+                }
+                if let Some(sampler) = &sampler {
+                    sampler.sample(&span, peer_addr.ip());
+                }
+                if shedding {
+                    attributes::Recorder::new(&span).set_attribute("load.shedding", true);
+                }
 
                 tokio::spawn(
                     async move {
-                        match client::handle_client(stream, peer_addr, args.password_chance).await {
-                            // todo
-                            Ok(_client_info) => {
+                        let _load_guard = load_guard;
+                        let started = SystemTime::now();
+                        let mut client_info = client::ClientInfo::default();
+
+                        let session = client::Session {
+                            id: session_id,
+                            peer_addr,
+                            local_addr,
+                            persona,
+                            world,
+                        };
+
+                        let (result, enriched) = tokio::join!(
+                            client::handle_client(
+                                stream,
+                                &session,
+                                &args,
+                                &history,
+                                &mut client_info,
+                            ),
+                            enrichment.enrich(peer_addr.ip()),
+                        );
+
+                        attributes::Recorder::new(&tracing::Span::current())
+                            .record("outcome", error::outcome(&result));
+                        match &result {
+                            Ok(()) => {
                                 info!("Client disconnected.");
                             }
                             Err(error) => {
                                 warn!("Client unexpectedly disconnected: {error}");
                             }
                         }
+
+                        sinks.send(sink::SessionEvent::new(
+                            &session,
+                            started,
+                            &client_info,
+                            &enriched,
+                            &result,
+                        ));
                     }
-                    .instrument(trace_span!(
-                        "client",
-                        %peer_addr,
-                        version = field::Empty,
-                        password = field::Empty,
-                        player_name = field::Empty,
-                        player_uuid = field::Empty
-                    )),
+                    .instrument(span),
                 );
             }
         }
@@ -112,62 +657,180 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-fn setup() -> Result<Args> {
+async fn setup(
+    args: Args,
+) -> Result<(
+    Args,
+    Option<sentry::ClientInitGuard>,
+    Option<port_mapping::PortMapping>,
+)> {
+    // console_subscriber::init();
+
+    attributes::init(&args.attributes);
+
+    // mapped before the tracing is set up so the external address can go in the resource
+    let port_mapping = port_mapping::PortMapping::create(&args.port_mapping, args.address())
+        .await
+        .wrap_err("Failed to map port")?;
+
+    match setup_tracing(&args, port_mapping.as_ref()) {
+        Ok(sentry) => {
+            if let Some(port_mapping) = &port_mapping {
+                info!("Mapped external address {}", port_mapping.external);
+            }
+
+            Ok((args, sentry, port_mapping))
+        }
+        Err(error) => {
+            if let Some(port_mapping) = &port_mapping {
+                port_mapping.remove().await;
+            }
+
+            Err(error)
+        }
+    }
+}
+
+/// Set up sentry, logging & opentelemetry.
+fn setup_tracing(
+    args: &Args,
+    port_mapping: Option<&port_mapping::PortMapping>,
+) -> Result<Option<sentry::ClientInitGuard>> {
     use opentelemetry::trace::TracerProvider as _;
 
-    color_eyre::install()?;
-    // console_subscriber::init();
+    let sentry = args.sentry.dsn.as_ref().map(|dsn| {
+        sentry::init((
+            dsn.as_str(),
+            sentry::ClientOptions {
+                release: sentry::release_name!(),
+                environment: args.sentry.environment.clone().map(Into::into),
+                ..Default::default()
+            },
+        ))
+    });
 
-    let args = Args::parse();
+    // sentry layer if a dsn is set, only our own errors are reported,
+    // alerts are about what clients are doing so they're left out
+    let sentry_layer = sentry.is_some().then(|| {
+        sentry_tracing::layer()
+            .event_filter(|metadata| match *metadata.level() {
+                tracing::Level::ERROR if metadata.fields().field("alert").is_none() => {
+                    sentry_tracing::EventFilter::Event
+                }
+                tracing::Level::WARN => sentry_tracing::EventFilter::Breadcrumb,
+                _ => sentry_tracing::EventFilter::Ignore,
+            })
+            .span_filter(|_| false)
+            .with_filter(
+                tracing_subscriber::filter::Targets::from_str("bottled_honey=warn").unwrap(),
+            )
+    });
+
+    // snmp trap layer if any receivers are set, only alerts are sent as traps
+    let snmp_layer = snmp::TrapLayer::from_args(&args.snmp)?.map(|layer| {
+        layer.with_filter(
+            tracing_subscriber::filter::Targets::from_str("bottled_honey=error").unwrap(),
+        )
+    });
+
+    // zabbix layer if a server is set, alerts are pushed as they happen
+    // while the sink pushes session counters
+    let zabbix_layer = sink::ZabbixAlerts::from_args(&args.zabbix).map(|layer| {
+        layer.with_filter(
+            tracing_subscriber::filter::Targets::from_str("bottled_honey=error").unwrap(),
+        )
+    });
 
     // stdout logging layer set with RUST_LOG, default's to logging all info & higher events
-    let registry = tracing_subscriber::registry().with(
-        tracing_subscriber::fmt::layer().with_filter(
-            EnvFilter::builder()
-                .with_default_directive(tracing::level_filters::LevelFilter::INFO.into())
-                .from_env_lossy(),
-        ),
-    );
-
-    // opentelemetry tracing layer if an otel endpoint is set, sends all trace & higher events
+    let registry = tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer().with_filter(
+                EnvFilter::builder()
+                    .with_default_directive(tracing::level_filters::LevelFilter::INFO.into())
+                    .from_env_lossy(),
+            ),
+        )
+        .with(sentry_layer)
+        .with(snmp_layer)
+        .with(zabbix_layer);
+
+    // opentelemetry tracing layer & metrics if an otel endpoint is set, sends all trace & higher events
     if let Some(endpoint) = &args.opentelemetry.endpoint {
+        let mut resource = vec![opentelemetry::KeyValue::new(
+            opentelemetry_semantic_conventions::resource::SERVICE_NAME,
+            "bottled_honey",
+        )];
+        if let Some(port_mapping) = port_mapping {
+            resource.push(opentelemetry::KeyValue::new(
+                "bottled_honey.external_address",
+                port_mapping.external.to_string(),
+            ));
+        }
+        resource.extend(
+            args.labels
+                .iter()
+                .map(|(key, value)| opentelemetry::KeyValue::new(key.clone(), value.clone())),
+        );
+        let resource = opentelemetry_sdk::Resource::new(resource);
+
         let trace_config = opentelemetry_sdk::trace::Config::default()
-            .with_resource(opentelemetry_sdk::Resource::new(vec![
-                opentelemetry::KeyValue::new(
-                    opentelemetry_semantic_conventions::resource::SERVICE_NAME,
-                    "bottled_honey",
-                ),
-            ]))
-            .with_sampler(opentelemetry_sdk::trace::Sampler::AlwaysOn);
-
-        let trace_exporter = opentelemetry_otlp::new_exporter()
-            .http()
-            .with_http_client(reqwest::Client::new())
-            .with_endpoint(endpoint);
-
-        // extra headers to be sent
-        // expects format of "key=val,key=val"
-        // keys can't contain equal signs nd keys or values can't contain commas
-        let trace_exporter = if let Some(headers) = &args.opentelemetry.headers {
-            trace_exporter.with_headers(
-                headers
-                    .split(',')
-                    .filter_map(|kv| {
-                        kv.split_once('=')
-                            .map(|(k, v)| (k.to_owned(), v.to_owned()))
-                    })
-                    .collect::<std::collections::HashMap<_, _>>(),
-            )
-        } else {
-            trace_exporter
+            .with_resource(resource.clone())
+            .with_sampler(sampling::SessionSampler);
+
+        let exporter = |endpoint: &str| {
+            let exporter = opentelemetry_otlp::new_exporter()
+                .http()
+                .with_http_client(reqwest::Client::new())
+                .with_endpoint(endpoint);
+
+            // extra headers to be sent
+            // expects format of "key=val,key=val"
+            // keys can't contain equal signs nd keys or values can't contain commas
+            if let Some(headers) = &args.opentelemetry.headers {
+                exporter.with_headers(
+                    headers
+                        .split(',')
+                        .filter_map(|kv| {
+                            kv.split_once('=')
+                                .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                        })
+                        .collect::<std::collections::HashMap<_, _>>(),
+                )
+            } else {
+                exporter
+            }
         };
 
         let tracer = opentelemetry_otlp::new_pipeline()
             .tracing()
             .with_trace_config(trace_config)
-            .with_exporter(trace_exporter)
+            .with_exporter(exporter(endpoint))
             .install_batch(opentelemetry_sdk::runtime::Tokio)?;
 
+        let metrics_endpoint = args
+            .opentelemetry
+            .metrics_endpoint
+            .clone()
+            .unwrap_or_else(|| {
+                let base = endpoint.trim_end_matches('/');
+                format!(
+                    "{}/v1/metrics",
+                    base.strip_suffix("/v1/traces").unwrap_or(base)
+                )
+            });
+
+        // metrics only go out over otlp, the instruments being the enrich.*, sink.* & load.*
+        // ones. none of them carry exemplars linking back to a session's trace as the sdk
+        // doesn't record them yet, once it does anything recorded inside the client span
+        // would pick its trace up without extra plumbing.
+        let meter_provider = opentelemetry_otlp::new_pipeline()
+            .metrics(opentelemetry_sdk::runtime::Tokio)
+            .with_resource(resource)
+            .with_exporter(exporter(&metrics_endpoint))
+            .build()?;
+
+        opentelemetry::global::set_meter_provider(meter_provider);
+
         let tracing_layer = tracing_opentelemetry::layer()
             .with_tracer(tracer.tracer("bottled_honey"))
             .with_filter(
@@ -179,5 +842,5 @@ fn setup() -> Result<Args> {
         registry.init();
     }
 
-    Ok(args)
+    Ok(sentry)
 }
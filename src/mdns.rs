@@ -0,0 +1,533 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bytes::{Buf, BufMut, BytesMut};
+use clap::Parser;
+use color_eyre::eyre::{bail, Context, Result};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+use tracing::{debug, info, warn};
+
+use crate::net;
+
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+
+const SERVICE_TYPE: &str = "_terraria._tcp.local";
+const SERVICE_ENUMERATION: &str = "_services._dns-sd._udp.local";
+
+// same as the usual responders so the records don't stand out
+const HOST_TTL: u32 = 120;
+const SERVICE_TTL: u32 = 4500;
+
+/// How long a query still counts when matching it up with a connection.
+const QUERY_WINDOW: Duration = Duration::from_secs(60 * 60);
+const TRACKED_ADDRESSES: usize = 10_000;
+
+// rfc 6762 asks for records to be multicast at most once a second,
+// the same goes for answering any one querier
+const RESPONSE_INTERVAL: Duration = Duration::from_secs(1);
+// across every querier, anything more is a flood rather than a lan looking around
+const RESPONSES_PER_SECOND: u32 = 10;
+// wait this long after failing to receive, errors like running out of buffers tend to stick
+const RECEIVE_ERROR_DELAY: Duration = Duration::from_secs(1);
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+// top bit of the class, set on records that replace any cached ones
+const CACHE_FLUSH: u16 = 0x8000;
+
+#[derive(Debug, Parser)]
+pub struct MdnsArgs {
+    /// mDNS name.
+    ///
+    /// Advertise the honeypot on the local network over mDNS as a Terraria server
+    /// with this name, so anything looking around the LAN finds it.
+    #[arg(env = "MDNS_NAME", long = "mdns-name")]
+    name: Option<String>,
+
+    /// mDNS hostname.
+    ///
+    /// Hostname the advertised server points at, .local is added on.
+    #[arg(
+        env = "MDNS_HOSTNAME",
+        long = "mdns-hostname",
+        default_value = "terraria-server"
+    )]
+    hostname: String,
+}
+
+/// Answers mDNS queries for the decoy server & remembers who asked.
+pub struct Mdns {
+    socket: UdpSocket,
+    instance: String,
+    host: String,
+    address: SocketAddrV4,
+    /// Network & mask of the advertised address, queries from outside it are ignored.
+    subnet: Option<(Ipv4Addr, Ipv4Addr)>,
+    queriers: Mutex<HashMap<IpAddr, Querier>>,
+    /// When the records were last multicast.
+    multicast: Mutex<Option<Instant>>,
+    /// Start of the current second & responses sent in it.
+    window: Mutex<(Instant, u32)>,
+}
+
+struct Querier {
+    /// When the address last asked about the server.
+    queried: Instant,
+    /// When the address was last answered directly.
+    answered: Option<Instant>,
+}
+
+impl Mdns {
+    pub async fn start(args: &MdnsArgs, address: SocketAddrV4) -> Result<Option<Arc<Self>>> {
+        let Some(name) = &args.name else {
+            return Ok(None);
+        };
+
+        // each has to fit in a single dns label
+        if name.len() > 63 || args.hostname.len() > 63 {
+            bail!("mDNS name & hostname can't be longer than 63 bytes");
+        }
+
+        let socket = bind().wrap_err("Failed to bind mDNS socket")?;
+        let address = SocketAddrV4::new(
+            net::local_ip(*address.ip(), (MDNS_GROUP, MDNS_PORT).into()).await?,
+            address.port(),
+        );
+
+        let subnet = net::local_subnet(*address.ip());
+        if subnet.is_none() {
+            warn!("Couldn't find the mDNS subnet, only answering private & link local addresses.");
+        }
+
+        let mdns = Arc::new(Self {
+            socket,
+            instance: format!("{name}.{SERVICE_TYPE}"),
+            host: format!("{}.local", args.hostname),
+            address,
+            subnet,
+            queriers: Mutex::new(HashMap::new()),
+            multicast: Mutex::new(None),
+            window: Mutex::new((Instant::now(), 0)),
+        });
+
+        info!("Advertising {:?} over mDNS at {address}", mdns.instance);
+        tokio::spawn(mdns.clone().run());
+
+        Ok(Some(mdns))
+    }
+
+    /// Whether `ip` asked about the server recently.
+    pub fn queried(&self, ip: IpAddr) -> bool {
+        self.queriers
+            .lock()
+            .unwrap()
+            .get(&ip)
+            .is_some_and(|querier| querier.queried.elapsed() < QUERY_WINDOW)
+    }
+
+    async fn run(self: Arc<Self>) {
+        // announce twice like a real responder would, a second apart
+        for _ in 0..2 {
+            self.respond(None, (MDNS_GROUP, MDNS_PORT).into()).await;
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+
+        let mut buffer = [0; 9000];
+        loop {
+            let (length, source) = match self.socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(error) => {
+                    warn!("Failed to receive mDNS packet: {error}");
+                    tokio::time::sleep(RECEIVE_ERROR_DELAY).await;
+                    continue;
+                }
+            };
+
+            // the socket's bound to every interface, so anything from outside the lan is
+            // dropped rather than letting it bounce responses off the honeypot (rfc 6762 11)
+            let SocketAddr::V4(source) = source else {
+                continue;
+            };
+            if !self.on_link(*source.ip()) {
+                debug!("Ignoring mDNS packet from off link address {source}");
+                continue;
+            }
+
+            let Some((id, questions)) = parse_query(&buffer[..length]) else {
+                continue;
+            };
+
+            let asked = questions
+                .iter()
+                .filter(|(name, kind)| self.answers(name, *kind))
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>();
+            if asked.is_empty() {
+                continue;
+            }
+
+            info!("mDNS query from {source} for {}", asked.join(", "));
+
+            // queries from anywhere but 5353 are one-shot lookups expecting a direct reply
+            let multicast = source.port() == MDNS_PORT;
+            if !self.remember((*source.ip()).into(), multicast) {
+                debug!("Not answering mDNS query from {source}, answered too recently");
+                continue;
+            }
+
+            if multicast {
+                self.respond(None, (MDNS_GROUP, MDNS_PORT).into()).await;
+            } else {
+                self.respond(Some(id), source.into()).await;
+            }
+        }
+    }
+
+    /// Whether `ip` is on the same link as the advertised address.
+    fn on_link(&self, ip: Ipv4Addr) -> bool {
+        if ip.is_loopback() {
+            return true;
+        }
+
+        match self.subnet {
+            Some((network, mask)) => u32::from(ip) & u32::from(mask) == u32::from(network),
+            None => ip.is_private() || ip.is_link_local(),
+        }
+    }
+
+    /// Whether a question about `name` should get a response.
+    fn answers(&self, name: &str, kind: u16) -> bool {
+        let is = |other: &str| name.eq_ignore_ascii_case(other);
+
+        match kind {
+            TYPE_PTR => is(SERVICE_ENUMERATION) || is(SERVICE_TYPE),
+            TYPE_SRV | TYPE_TXT => is(&self.instance),
+            TYPE_A => is(&self.host),
+            TYPE_ANY => is(SERVICE_TYPE) || is(&self.instance) || is(&self.host),
+            _ => false,
+        }
+    }
+
+    /// Remember `ip` asked about the server, returns whether it should be answered.
+    fn remember(&self, ip: IpAddr, multicast: bool) -> bool {
+        let now = Instant::now();
+        let mut queriers = self.queriers.lock().unwrap();
+
+        if queriers.len() >= TRACKED_ADDRESSES && !queriers.contains_key(&ip) {
+            queriers.retain(|_, querier| querier.queried.elapsed() < QUERY_WINDOW);
+        }
+
+        let querier = queriers.entry(ip).or_insert(Querier {
+            queried: now,
+            answered: None,
+        });
+        querier.queried = now;
+
+        let recent = |at: Option<Instant>| at.is_some_and(|at| at.elapsed() < RESPONSE_INTERVAL);
+        if multicast {
+            let mut multicast = self.multicast.lock().unwrap();
+            if recent(*multicast) || !self.take_response() {
+                return false;
+            }
+            *multicast = Some(now);
+        } else {
+            if recent(querier.answered) || !self.take_response() {
+                return false;
+            }
+            querier.answered = Some(now);
+        }
+
+        true
+    }
+
+    fn take_response(&self) -> bool {
+        let mut window = self.window.lock().unwrap();
+        if window.0.elapsed() >= Duration::from_secs(1) {
+            *window = (Instant::now(), 0);
+        }
+
+        if window.1 >= RESPONSES_PER_SECOND {
+            return false;
+        }
+
+        window.1 += 1;
+        true
+    }
+
+    /// Send every record, they're small enough that picking & choosing isn't worth it.
+    async fn respond(&self, id: Option<u16>, destination: SocketAddr) {
+        let mut records = BytesMut::new();
+
+        record(
+            &mut records,
+            SERVICE_ENUMERATION,
+            TYPE_PTR,
+            CLASS_IN,
+            SERVICE_TTL,
+        );
+        with_length(&mut records, |data| name(data, SERVICE_TYPE));
+
+        record(&mut records, SERVICE_TYPE, TYPE_PTR, CLASS_IN, SERVICE_TTL);
+        with_length(&mut records, |data| name(data, &self.instance));
+
+        let class = CLASS_IN | CACHE_FLUSH;
+        record(&mut records, &self.instance, TYPE_SRV, class, HOST_TTL);
+        with_length(&mut records, |data| {
+            // priority, weight, port, target
+            data.put_u16(0);
+            data.put_u16(0);
+            data.put_u16(self.address.port());
+            name(data, &self.host);
+        });
+
+        record(&mut records, &self.instance, TYPE_TXT, class, SERVICE_TTL);
+        with_length(&mut records, |data| data.put_u8(0));
+
+        record(&mut records, &self.host, TYPE_A, class, HOST_TTL);
+        with_length(&mut records, |data| {
+            data.put_slice(&self.address.ip().octets())
+        });
+
+        let mut packet = BytesMut::new();
+        packet.put_u16(id.unwrap_or(0));
+        // response & authoritative
+        packet.put_u16(0x8400);
+        // questions, answers, authority, additional
+        packet.put_u16(0);
+        packet.put_u16(5);
+        packet.put_u16(0);
+        packet.put_u16(0);
+        packet.put(records);
+
+        if let Err(error) = self.socket.send_to(&packet, destination).await {
+            warn!("Failed to send mDNS response to {destination}: {error}");
+        }
+    }
+}
+
+/// Port 5353 is usually taken by the system's own responder, so the socket has to be shared.
+fn bind() -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, MDNS_PORT)).into())?;
+    socket.join_multicast_v4(&MDNS_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+
+    UdpSocket::from_std(socket.into())
+}
+
+/// Id & questions of a query, or none if it isn't one.
+fn parse_query(packet: &[u8]) -> Option<(u16, Vec<(String, u16)>)> {
+    let mut reader = packet;
+    if reader.remaining() < 12 {
+        return None;
+    }
+
+    let id = reader.get_u16();
+    let flags = reader.get_u16();
+    let questions = reader.get_u16();
+    reader.advance(6);
+
+    // responses from other hosts come through here too
+    if flags & 0x8000 != 0 {
+        return None;
+    }
+
+    let mut parsed = Vec::new();
+    let mut offset = 12;
+    for _ in 0..questions {
+        let (name, next) = read_name(packet, offset)?;
+        let mut reader = packet.get(next..next + 4)?;
+        parsed.push((name, reader.get_u16()));
+        offset = next + 4;
+    }
+
+    debug!("mDNS query: {parsed:?}");
+    Some((id, parsed))
+}
+
+/// Read a possibly compressed name at `offset`, returning it & the offset just after it.
+fn read_name(packet: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+
+    // bounded so a pointer loop can't spin forever
+    for _ in 0..128 {
+        let length = *packet.get(offset)? as usize;
+
+        if length == 0 {
+            return Some((labels.join("."), end.unwrap_or(offset + 1)));
+        }
+
+        if length & 0xc0 == 0xc0 {
+            let pointer = u16::from_be_bytes([*packet.get(offset)?, *packet.get(offset + 1)?]);
+            end.get_or_insert(offset + 2);
+            offset = (pointer & 0x3fff) as usize;
+            continue;
+        }
+
+        let label = packet.get(offset + 1..offset + 1 + length)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        offset += 1 + length;
+    }
+
+    None
+}
+
+fn name(buffer: &mut BytesMut, name: &str) {
+    // instance names can have dots in them, the service type can't
+    let (instance, rest) = match name.strip_suffix(SERVICE_TYPE) {
+        Some(instance) if !instance.is_empty() => (instance.strip_suffix('.'), SERVICE_TYPE),
+        _ => (None, name),
+    };
+
+    for label in instance.into_iter().chain(rest.split('.')) {
+        buffer.put_u8(label.len() as u8);
+        buffer.put_slice(label.as_bytes());
+    }
+    buffer.put_u8(0);
+}
+
+fn record(buffer: &mut BytesMut, owner: &str, kind: u16, class: u16, ttl: u32) {
+    name(buffer, owner);
+    buffer.put_u16(kind);
+    buffer.put_u16(class);
+    buffer.put_u32(ttl);
+}
+
+/// Write record data prefixed with its length.
+fn with_length(buffer: &mut BytesMut, write: impl FnOnce(&mut BytesMut)) {
+    let mut data = BytesMut::new();
+    write(&mut data);
+    buffer.put_u16(data.len() as u16);
+    buffer.put(data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(name: &str) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        for label in name.split('.') {
+            encoded.push(label.len() as u8);
+            encoded.extend_from_slice(label.as_bytes());
+        }
+        encoded.push(0);
+        encoded
+    }
+
+    fn query(questions: &[(&[u8], u16)]) -> Vec<u8> {
+        let mut packet = vec![0x12, 0x34, 0, 0, 0, questions.len() as u8, 0, 0, 0, 0, 0, 0];
+        for (name, kind) in questions {
+            packet.extend_from_slice(name);
+            packet.extend_from_slice(&kind.to_be_bytes());
+            packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+        }
+        packet
+    }
+
+    #[test]
+    fn parses_question() {
+        let packet = query(&[(&labels(SERVICE_TYPE), TYPE_PTR)]);
+
+        assert_eq!(
+            parse_query(&packet),
+            Some((0x1234, vec![(SERVICE_TYPE.to_owned(), TYPE_PTR)]))
+        );
+    }
+
+    #[test]
+    fn follows_compression_pointers() {
+        // second question is "test" followed by a pointer back to the first name
+        let mut second = vec![4];
+        second.extend_from_slice(b"test");
+        second.extend_from_slice(&[0xc0, 12]);
+        let packet = query(&[(&labels(SERVICE_TYPE), TYPE_PTR), (&second, TYPE_SRV)]);
+
+        let (_, questions) = parse_query(&packet).unwrap();
+        assert_eq!(questions[1], (format!("test.{SERVICE_TYPE}"), TYPE_SRV));
+    }
+
+    #[test]
+    fn read_name_ends_after_first_pointer() {
+        let mut packet = labels("local");
+        let pointer = packet.len();
+        packet.extend_from_slice(&[0xc0, 0, 0xff]);
+
+        assert_eq!(
+            read_name(&packet, pointer),
+            Some(("local".to_owned(), pointer + 2))
+        );
+    }
+
+    #[test]
+    fn rejects_pointer_loops() {
+        // pointing at itself
+        assert_eq!(read_name(&[0xc0, 0], 0), None);
+        // two pointers pointing at each other
+        assert_eq!(read_name(&[0xc0, 2, 0xc0, 0], 0), None);
+    }
+
+    #[test]
+    fn rejects_truncated_labels() {
+        assert_eq!(read_name(&[5, b'l', b'o'], 0), None);
+        // label without the terminating zero
+        assert_eq!(read_name(&[2, b'h', b'i'], 0), None);
+        // half a pointer
+        assert_eq!(read_name(&[0xc0], 0), None);
+    }
+
+    #[test]
+    fn rejects_out_of_range_offsets() {
+        assert_eq!(read_name(&[0], 1), None);
+        assert_eq!(read_name(&[0xc0, 0xff], 0), None);
+        assert_eq!(read_name(&[0xff, 0xff], 0), None);
+    }
+
+    #[test]
+    fn rejects_short_or_truncated_queries() {
+        assert_eq!(parse_query(&[0; 11]), None);
+
+        // claims a question that isn't there
+        let mut packet = query(&[]);
+        packet[5] = 1;
+        assert_eq!(parse_query(&packet), None);
+
+        // name without a type & class
+        let mut packet = query(&[(&labels(SERVICE_TYPE), TYPE_PTR)]);
+        packet.truncate(packet.len() - 3);
+        assert_eq!(parse_query(&packet), None);
+    }
+
+    #[test]
+    fn ignores_responses() {
+        let mut packet = query(&[(&labels(SERVICE_TYPE), TYPE_PTR)]);
+        packet[2] = 0x84;
+
+        assert_eq!(parse_query(&packet), None);
+    }
+
+    #[test]
+    fn encodes_instance_names_with_dots() {
+        let mut buffer = BytesMut::new();
+        name(&mut buffer, &format!("my.server.{SERVICE_TYPE}"));
+
+        let mut expected = vec![9];
+        expected.extend_from_slice(b"my.server");
+        expected.extend_from_slice(&labels(SERVICE_TYPE));
+        assert_eq!(&buffer[..], &expected[..]);
+    }
+}
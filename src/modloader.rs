@@ -0,0 +1,111 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use bytes::{BufMut, Bytes};
+
+use crate::packet::{self, BufMutExt};
+
+/// What tModLoader clients send as their ConnectRequest signature, followed by the version.
+pub const SIGNATURE_PREFIX: &str = "tModLoader v";
+
+/// SyncMods ($FB), the server's mod list going one way & the client saying it's done
+/// syncing going the other.
+pub const SYNC_MODS: u8 = 0xFB;
+/// ModFile ($FC), the client asking to download a mod it doesn't have.
+pub const MOD_FILE: u8 = 0xFC;
+
+// far longer than any real mod's name or version, & with the mod limit keeps the whole
+// mod list in one packet
+const MAX_FIELD_LENGTH: usize = 128;
+pub const MAX_FAKE_MODS: usize = 200;
+
+/// Mod the server claims to have, sent to tModLoader clients.
+#[derive(Debug, Clone)]
+pub struct FakeMod {
+    pub name: String,
+    pub version: String,
+}
+
+impl FakeMod {
+    /// Mod file hash, the real thing is a sha1 of the .tmod file but anything
+    /// that's the same every time will do.
+    fn hash(&self) -> [u8; 20] {
+        let mut hasher = DefaultHasher::new();
+        (&self.name, &self.version).hash(&mut hasher);
+
+        let mut hash = [0; 20];
+        fastrand::Rng::with_seed(hasher.finish()).fill(&mut hash);
+        hash
+    }
+}
+
+impl std::fmt::Display for FakeMod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}@{}", self.name, self.version)
+    }
+}
+
+/// Parse a fake mod in the format of "name@version".
+pub fn parse_fake_mod(value: &str) -> Result<FakeMod, String> {
+    let (name, version) = value
+        .split_once('@')
+        .filter(|(name, version)| !name.is_empty() && !version.is_empty())
+        .ok_or_else(|| "expected the format of \"name@version\"".to_owned())?;
+    packet::check_string_length(name, MAX_FIELD_LENGTH)?;
+    packet::check_string_length(version, MAX_FIELD_LENGTH)?;
+
+    Ok(FakeMod {
+        name: name.to_owned(),
+        version: version.to_owned(),
+    })
+}
+
+/// What a tModLoader client did with the mod list it was sent.
+#[derive(Debug, Default)]
+pub struct ModSync {
+    /// Mods the client said it already has, by finishing syncing without downloading them.
+    pub installed: Vec<String>,
+    /// Mod the client asked to download, it's kicked straight after.
+    pub requested: Option<String>,
+}
+
+/// SyncMods ($FB) listing `mods`, as of tModLoader 1.4.4.
+pub fn sync_mods(mods: &[FakeMod]) -> Bytes {
+    packet::packet(SYNC_MODS, |buf| {
+        buf.put_i32_le(mods.len() as i32);
+
+        for r#mod in mods {
+            buf.put_string(&r#mod.name);
+            buf.put_string(&r#mod.version);
+            buf.put_slice(&r#mod.hash());
+            // signed by the mod browser
+            buf.put_u8(1);
+            // server side configs
+            buf.put_i32_le(0);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn longest_mod_list_fits_in_a_packet() {
+        let longest = "a".repeat(MAX_FIELD_LENGTH);
+        let r#mod = parse_fake_mod(&format!("{longest}@{longest}")).unwrap();
+
+        let packet = sync_mods(&vec![r#mod; MAX_FAKE_MODS]);
+        assert_eq!(
+            u16::from_le_bytes([packet[0], packet[1]]) as usize,
+            packet.len()
+        );
+    }
+
+    #[test]
+    fn overlong_mod_is_rejected() {
+        let longest = "a".repeat(MAX_FIELD_LENGTH + 1);
+
+        assert!(parse_fake_mod(&format!("{longest}@1.0")).is_err());
+        assert!(parse_fake_mod(&format!("Mod@{longest}")).is_err());
+    }
+}
@@ -0,0 +1,73 @@
+use std::net::{Ipv4Addr, SocketAddr};
+
+use color_eyre::eyre::{bail, Context, OptionExt, Result};
+use tokio::net::UdpSocket;
+
+/// A route from the kernel's routing table.
+struct Route {
+    destination: Ipv4Addr,
+    gateway: Ipv4Addr,
+    mask: Ipv4Addr,
+}
+
+/// Every ipv4 route, only linux exposes these without shelling out.
+fn routes() -> std::io::Result<Vec<Route>> {
+    let routes = std::fs::read_to_string("/proc/net/route")?;
+
+    // written out as the raw value, so it's in network order in memory
+    let address = |field: &str| {
+        u32::from_str_radix(field, 16)
+            .ok()
+            .map(|address| Ipv4Addr::from(address.to_ne_bytes()))
+    };
+
+    Ok(routes
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            Some(Route {
+                destination: address(fields.get(1)?)?,
+                gateway: address(fields.get(2)?)?,
+                mask: address(fields.get(7)?)?,
+            })
+        })
+        .collect())
+}
+
+/// Gateway of the default route.
+pub fn default_gateway() -> Result<Ipv4Addr> {
+    routes()
+        .wrap_err("Failed to read routes, set the gateway with --port-mapping-gateway")?
+        .into_iter()
+        .find(|route| route.destination.is_unspecified() && route.mask.is_unspecified())
+        .map(|route| route.gateway)
+        .ok_or_eyre("No default route found, set the gateway with --port-mapping-gateway")
+}
+
+/// Network & mask of the directly connected subnet `ip` is on, if it can be found.
+pub fn local_subnet(ip: Ipv4Addr) -> Option<(Ipv4Addr, Ipv4Addr)> {
+    routes()
+        .ok()?
+        .into_iter()
+        // no gateway means the hosts are reachable directly, no mask is the default route
+        .filter(|route| route.gateway.is_unspecified() && !route.mask.is_unspecified())
+        .find(|route| u32::from(ip) & u32::from(route.mask) == u32::from(route.destination))
+        .map(|route| (route.destination, route.mask))
+}
+
+/// Address used to reach `destination`, the bind address unless that's unspecified.
+pub async fn local_ip(bind: Ipv4Addr, destination: SocketAddr) -> Result<Ipv4Addr> {
+    if !bind.is_unspecified() {
+        return Ok(bind);
+    }
+
+    // connecting a udp socket doesn't send anything but does pick the interface
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.connect(destination).await?;
+
+    match socket.local_addr()? {
+        SocketAddr::V4(address) => Ok(*address.ip()),
+        SocketAddr::V6(address) => bail!("No ipv4 route to {destination}, got {address}"),
+    }
+}
@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use clap::{Parser, ValueEnum};
+use serde_json::json;
+use tracing::warn;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Parser)]
+pub struct NotifyArgs {
+    /// Notification webhooks.
+    ///
+    /// Webhooks to post notifications like the daily digest to.
+    /// (can be passed multiple times)
+    #[arg(env = "NOTIFY_WEBHOOK", long = "notify-webhook")]
+    webhooks: Vec<String>,
+
+    /// Notification format.
+    ///
+    /// Shape of the json posted to the webhooks.
+    #[arg(
+        env = "NOTIFY_FORMAT",
+        long = "notify-format",
+        value_enum,
+        default_value_t = NotifyFormat::Json
+    )]
+    format: NotifyFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum NotifyFormat {
+    /// {"title": "...", "message": "..."}
+    Json,
+    /// Slack incoming webhook.
+    Slack,
+    /// Discord webhook.
+    Discord,
+}
+
+/// Sends notifications to every configured webhook.
+pub struct Notifier {
+    client: reqwest::Client,
+    webhooks: Vec<String>,
+    format: NotifyFormat,
+}
+
+impl Notifier {
+    pub fn from_args(args: &NotifyArgs) -> Option<Self> {
+        if args.webhooks.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("failed to build http client"),
+            webhooks: args.webhooks.clone(),
+            format: args.format,
+        })
+    }
+
+    pub async fn notify(&self, title: &str, message: &str) {
+        let body = match self.format {
+            NotifyFormat::Json => json!({ "title": title, "message": message }),
+            NotifyFormat::Slack => json!({ "text": format!("*{title}*\n{message}") }),
+            NotifyFormat::Discord => json!({ "content": format!("**{title}**\n{message}") }),
+        };
+
+        for webhook in &self.webhooks {
+            let result = self
+                .client
+                .post(webhook)
+                .json(&body)
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status);
+
+            // a missed notification isn't worth much more than a warning
+            if let Err(error) = result {
+                warn!("Failed to send notification: {error}");
+            }
+        }
+    }
+}
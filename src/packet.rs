@@ -0,0 +1,262 @@
+use std::fmt::Write as _;
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// Name of a packet sent by clients, for the packets we know about.
+pub(crate) fn name(id: u8) -> Option<&'static str> {
+    Some(match id {
+        0x01 => "ConnectRequest",
+        0x04 => "PlayerInfo",
+        0x05 => "PlayerInventorySlot",
+        0x06 => "RequestWorldData",
+        0x08 => "RequestEssentialTiles",
+        0x0C => "PlayerSpawn",
+        0x0D => "PlayerControls",
+        0x10 => "PlayerLife",
+        0x11 => "TileManipulation",
+        0x15 => "ItemDrop",
+        0x1B => "ProjectileUpdate",
+        0x26 => "SendPassword",
+        0x2A => "PlayerMana",
+        0x32 => "PlayerBuffs",
+        0x44 => "ClientUUID",
+        0x52 => "NetModules",
+        0x93 => "SyncLoadout",
+        0xFA => "ModPacket",
+        0xFB => "SyncMods",
+        0xFC => "ModFile",
+        _ => return None,
+    })
+}
+
+/// Lowercase hex encoding of raw packet bytes.
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{byte:02x}");
+    }
+
+    hex
+}
+
+/// Longest string operators can have sent to clients, well past anything the client shows
+/// but short enough that every packet one goes in still fits its length prefix.
+pub(crate) const MAX_STRING_LENGTH: usize = 1024;
+
+/// Make sure a string operators want sent to clients isn't too long to fit in a packet.
+pub(crate) fn check_string_length(value: &str, max: usize) -> Result<(), String> {
+    if value.len() > max {
+        return Err(format!(
+            "too long to send, {} bytes when the most is {max}",
+            value.len()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Parse a string operators want sent to clients.
+pub(crate) fn parse_string(value: &str) -> Result<String, String> {
+    check_string_length(value, MAX_STRING_LENGTH)?;
+    Ok(value.to_owned())
+}
+
+/// Build a packet with the given id, `body` is expected to write the packet's body.
+///
+/// Takes care of the length prefix so callers don't need to work it out.
+pub(crate) fn packet(id: u8, body: impl FnOnce(&mut BytesMut)) -> Bytes {
+    let mut buf = BytesMut::with_capacity(64);
+    buf.put_u16_le(0);
+    buf.put_u8(id);
+
+    body(&mut buf);
+
+    // operator strings are all checked when parsing args, so only a bug could get here
+    let length = u16::try_from(buf.len()).expect("packet too large for length prefix");
+    buf[..2].copy_from_slice(&length.to_le_bytes());
+
+    buf.freeze()
+}
+
+pub(crate) trait BufMutExt: BufMut {
+    /// Write a string the same way .NET's `BinaryWriter` does,
+    /// a 7 bit encoded length followed by the utf8 bytes.
+    fn put_string(&mut self, string: &str) {
+        let mut length = string.len();
+        while length >= 0x80 {
+            self.put_u8((length as u8) | 0x80);
+            length >>= 7;
+        }
+        self.put_u8(length as u8);
+
+        self.put_slice(string.as_bytes());
+    }
+
+    /// Write a literal `NetworkText`.
+    fn put_network_text(&mut self, text: &str) {
+        // mode 0 = literal, no substitutions follow
+        self.put_u8(0);
+        self.put_string(text);
+    }
+}
+
+impl<B: BufMut> BufMutExt for B {}
+
+/// Details of the fake world sent to clients.
+#[derive(Debug, Clone)]
+pub(crate) struct World {
+    pub name: String,
+    pub id: i32,
+    pub width: i16,
+    pub height: i16,
+}
+
+impl World {
+    pub const SECTION_WIDTH: i16 = 200;
+    pub const SECTION_HEIGHT: i16 = 150;
+
+    pub fn spawn_tile(&self) -> (i16, i16) {
+        (self.width / 2, self.height / 4)
+    }
+
+    pub fn sections(&self) -> (i16, i16) {
+        (
+            self.width / Self::SECTION_WIDTH,
+            self.height / Self::SECTION_HEIGHT,
+        )
+    }
+}
+
+/// WorldInfo ($07), as of 1.4.4.
+///
+/// Almost everything here is zeroed, just enough to look like a fresh world.
+pub(crate) fn world_info(world: &World) -> Bytes {
+    world_info_at(world, 27000, true)
+}
+
+/// WorldInfo ($07) at a specific time of day.
+pub(crate) fn world_info_at(world: &World, time: i32, day: bool) -> Bytes {
+    packet(0x07, |buf| {
+        buf.put_i32_le(time);
+        // day time
+        buf.put_u8(day as u8);
+        // moon phase
+        buf.put_u8(0);
+
+        let (spawn_x, spawn_y) = world.spawn_tile();
+        buf.put_i16_le(world.width);
+        buf.put_i16_le(world.height);
+        buf.put_i16_le(spawn_x);
+        buf.put_i16_le(spawn_y);
+        // world surface & rock layer
+        buf.put_i16_le(spawn_y);
+        buf.put_i16_le(spawn_y + world.height / 8);
+
+        buf.put_i32_le(world.id);
+        buf.put_string(&world.name);
+        // game mode (classic)
+        buf.put_u8(0);
+        // unique id, derived from the world id so it's stable
+        buf.put_slice(&[world.id.to_le_bytes(); 4].concat());
+        // world generator version (1.4.4.9)
+        buf.put_u64_le(1_198_295_875_585);
+
+        // moon type, tree/biome backgrounds & back styles
+        buf.put_bytes(0, 1 + 13 + 3);
+        // wind speed
+        buf.put_f32_le(0.0);
+        // cloud count
+        buf.put_u8(0);
+        // tree x, tree style, cave back x, cave back style
+        buf.put_bytes(0, 3 * 4 + 4 + 3 * 4 + 4);
+        // tree tops
+        buf.put_bytes(0, 13);
+        // max raining
+        buf.put_f32_le(0.0);
+        // event/progression flags, sundial & moondial cooldowns
+        buf.put_bytes(0, 10 + 2);
+        // ore tiers
+        buf.put_bytes(0, 7 * 2);
+        // invasion type
+        buf.put_i8(0);
+        // lobby id
+        buf.put_u64_le(0);
+        // sandstorm severity
+        buf.put_f32_le(0.0);
+    })
+}
+
+/// CompleteConnectionAndSpawn ($31), tells the client it can go ahead and spawn.
+pub(crate) fn complete_connection_and_spawn() -> Bytes {
+    packet(0x31, |_| {})
+}
+
+/// FinishedConnectingToServer ($81).
+pub(crate) fn finished_connecting() -> Bytes {
+    packet(0x81, |_| {})
+}
+
+/// Kick ($02), the client is shown `reason` as it disconnects.
+pub(crate) fn kick(reason: &str) -> Bytes {
+    packet(0x02, |buf| buf.put_network_text(reason))
+}
+
+/// StatusText ($09), shown on the client's loading screen.
+pub(crate) fn status_text(max: i32, text: &str) -> Bytes {
+    packet(0x09, |buf| {
+        buf.put_i32_le(max);
+        buf.put_network_text(text);
+        // flags
+        buf.put_u8(0);
+    })
+}
+
+/// StatusText ($09) that says its text has substitutions but ends before them.
+///
+/// Fine right up until the end, so only clients that properly parse the text notice.
+pub(crate) fn malformed_status_text() -> Bytes {
+    packet(0x09, |buf| {
+        buf.put_i32_le(0);
+        // mode 1 = formattable, should be followed by a substitution count & substitutions
+        buf.put_u8(1);
+        buf.put_string("Loading {0}");
+    })
+}
+
+/// SendSection ($0A) for the given section, filled with `length` bytes of junk.
+///
+/// Sections are deflate compressed, this uses a single non-final stored block
+/// so it's at least the right shape but the client will never get to the end.
+pub(crate) fn junk_section(x: i16, y: i16, length: u16) -> Bytes {
+    let mut data = BytesMut::with_capacity(12 + length as usize);
+    data.put_i32_le(x as i32 * World::SECTION_WIDTH as i32);
+    data.put_i32_le(y as i32 * World::SECTION_HEIGHT as i32);
+    data.put_i16_le(World::SECTION_WIDTH);
+    data.put_i16_le(World::SECTION_HEIGHT);
+
+    let junk_start = data.len();
+    data.resize(junk_start + length as usize, 0);
+    fastrand::Rng::new().fill(&mut data[junk_start..]);
+
+    packet(0x0A, |buf| {
+        let data_length = data.len() as u16;
+        // non-final stored block header
+        buf.put_u8(0);
+        buf.put_u16_le(data_length);
+        buf.put_u16_le(!data_length);
+        buf.put_slice(&data);
+    })
+}
+
+/// Chat message from the server, sent through the text NetModule ($52).
+pub(crate) fn chat_message(text: &str) -> Bytes {
+    packet(0x52, |buf| {
+        // text module
+        buf.put_u16_le(1);
+        // author, 255 being the server
+        buf.put_u8(255);
+        buf.put_network_text(text);
+        // colour, same yellow the server uses for most messages
+        buf.put_slice(&[255, 240, 20]);
+    })
+}
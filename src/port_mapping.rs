@@ -0,0 +1,243 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddrV4},
+    time::Duration,
+};
+
+use clap::{Parser, ValueEnum};
+use color_eyre::eyre::{bail, eyre, Context, OptionExt, Result};
+use igd_next::aio::{tokio::Tokio, Gateway};
+use tokio::net::UdpSocket;
+use tracing::{info, warn};
+
+use crate::net;
+
+// leases are kept short & renewed so a crash doesn't leave the port open for long
+const LEASE: Duration = Duration::from_secs(60 * 60);
+
+const NAT_PMP_PORT: u16 = 5351;
+// rfc 6886 starts at 250ms & doubles, gives up a lot later than this though
+const NAT_PMP_ATTEMPTS: u32 = 4;
+const NAT_PMP_INITIAL_TIMEOUT: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Parser)]
+pub struct PortMappingArgs {
+    /// Port mapping.
+    ///
+    /// Ask the router to forward the honeypot port at startup & remove it again on shutdown,
+    /// for running behind a home router without setting up port forwarding by hand.
+    #[arg(env = "PORT_MAPPING", long = "port-mapping", value_enum)]
+    protocol: Option<MappingProtocol>,
+
+    /// Port mapping gateway.
+    ///
+    /// Router to send NAT-PMP requests to, defaults to the default route's gateway.
+    #[arg(env = "PORT_MAPPING_GATEWAY", long = "port-mapping-gateway")]
+    gateway: Option<Ipv4Addr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MappingProtocol {
+    /// UPnP IGD, found with SSDP.
+    Upnp,
+    /// NAT-PMP, sent to the gateway.
+    NatPmp,
+}
+
+enum Router {
+    Upnp(Gateway<Tokio>),
+    NatPmp(SocketAddrV4),
+}
+
+/// A port forwarded from the router to the honeypot.
+pub struct PortMapping {
+    router: Router,
+    local: SocketAddrV4,
+    /// Address clients reach the honeypot on.
+    pub external: SocketAddrV4,
+}
+
+impl PortMapping {
+    pub async fn create(args: &PortMappingArgs, address: SocketAddrV4) -> Result<Option<Self>> {
+        let Some(protocol) = args.protocol else {
+            return Ok(None);
+        };
+
+        let router = match protocol {
+            MappingProtocol::Upnp => Router::Upnp(
+                igd_next::aio::tokio::search_gateway(Default::default())
+                    .await
+                    .wrap_err("Failed to find a UPnP gateway")?,
+            ),
+            MappingProtocol::NatPmp => {
+                let gateway = match args.gateway {
+                    Some(gateway) => gateway,
+                    None => net::default_gateway()?,
+                };
+                Router::NatPmp(SocketAddrV4::new(gateway, NAT_PMP_PORT))
+            }
+        };
+
+        let gateway = match &router {
+            Router::Upnp(gateway) => gateway.addr,
+            Router::NatPmp(gateway) => (*gateway).into(),
+        };
+        let local = SocketAddrV4::new(net::local_ip(*address.ip(), gateway).await?, address.port());
+
+        let mut mapping = Self {
+            router,
+            local,
+            external: SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, address.port()),
+        };
+
+        let external_ip = mapping.external_ip().await?;
+        mapping.external.set_ip(external_ip);
+        mapping.map().await?;
+
+        Ok(Some(mapping))
+    }
+
+    /// Keep renewing the lease until the mapping's removed.
+    pub async fn keep_alive(&self) {
+        loop {
+            tokio::time::sleep(LEASE / 2).await;
+
+            if let Err(error) = self.map().await {
+                warn!("Failed to renew port mapping: {error:#}");
+            }
+        }
+    }
+
+    pub async fn remove(&self) {
+        let result = match &self.router {
+            Router::Upnp(gateway) => gateway
+                .remove_port(igd_next::PortMappingProtocol::TCP, self.external.port())
+                .await
+                .wrap_err("Failed to remove UPnP port mapping"),
+            Router::NatPmp(gateway) => nat_pmp_map(*gateway, self.local.port(), 0, Duration::ZERO)
+                .await
+                .map(|_| ()),
+        };
+
+        match result {
+            Ok(()) => info!("Removed port mapping for {}", self.external),
+            Err(error) => warn!("{error:#}"),
+        }
+    }
+
+    async fn external_ip(&self) -> Result<Ipv4Addr> {
+        match &self.router {
+            Router::Upnp(gateway) => {
+                match gateway
+                    .get_external_ip()
+                    .await
+                    .wrap_err("Failed to get external address from UPnP gateway")?
+                {
+                    IpAddr::V4(ip) => Ok(ip),
+                    IpAddr::V6(ip) => bail!("UPnP gateway has an ipv6 external address: {ip}"),
+                }
+            }
+            Router::NatPmp(gateway) => {
+                // external address request, version 0 & opcode 0
+                let response = nat_pmp_request(*gateway, &[0, 0]).await?;
+                let ip: [u8; 4] = response
+                    .get(8..12)
+                    .and_then(|ip| ip.try_into().ok())
+                    .ok_or_eyre("NAT-PMP external address response too short")?;
+                Ok(ip.into())
+            }
+        }
+    }
+
+    async fn map(&self) -> Result<()> {
+        match &self.router {
+            Router::Upnp(gateway) => gateway
+                .add_port(
+                    igd_next::PortMappingProtocol::TCP,
+                    self.external.port(),
+                    self.local.into(),
+                    LEASE.as_secs() as u32,
+                    "bottled_honey",
+                )
+                .await
+                .wrap_err("Failed to add UPnP port mapping"),
+            Router::NatPmp(gateway) => {
+                let external_port =
+                    nat_pmp_map(*gateway, self.local.port(), self.external.port(), LEASE).await?;
+
+                // routers are free to hand out a different port than asked for,
+                // don't leave it open for the rest of the lease when it's not the one wanted
+                if external_port != self.external.port() {
+                    let _ = nat_pmp_map(*gateway, self.local.port(), 0, Duration::ZERO).await;
+                    bail!(
+                        "NAT-PMP gateway mapped port {external_port} instead of {}",
+                        self.external.port()
+                    );
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Map a tcp port, returns the external port the gateway picked.
+/// A lifetime of zero removes the mapping.
+async fn nat_pmp_map(
+    gateway: SocketAddrV4,
+    internal_port: u16,
+    external_port: u16,
+    lifetime: Duration,
+) -> Result<u16> {
+    // version 0, opcode 2 (tcp), 2 reserved bytes, internal port, external port, lifetime
+    let mut request = vec![0, 2, 0, 0];
+    request.extend_from_slice(&internal_port.to_be_bytes());
+    request.extend_from_slice(&external_port.to_be_bytes());
+    request.extend_from_slice(&(lifetime.as_secs() as u32).to_be_bytes());
+
+    let response = nat_pmp_request(gateway, &request).await?;
+    let port = response
+        .get(10..12)
+        .ok_or_eyre("NAT-PMP mapping response too short")?;
+    Ok(u16::from_be_bytes([port[0], port[1]]))
+}
+
+/// Send a NAT-PMP request, retrying with backoff, and return the response if it was successful.
+async fn nat_pmp_request(gateway: SocketAddrV4, request: &[u8]) -> Result<Vec<u8>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket
+        .connect(gateway)
+        .await
+        .wrap_err("Failed to connect to NAT-PMP gateway")?;
+
+    let mut timeout = NAT_PMP_INITIAL_TIMEOUT;
+    let mut buffer = [0; 16];
+
+    for _ in 0..NAT_PMP_ATTEMPTS {
+        socket.send(request).await?;
+
+        let length = match tokio::time::timeout(timeout, socket.recv(&mut buffer)).await {
+            Ok(length) => length?,
+            Err(_) => {
+                timeout *= 2;
+                continue;
+            }
+        };
+        let response = &buffer[..length];
+
+        // responses echo the opcode plus 128, then a result code
+        if response.len() < 4 || response[1] != request[1] + 128 {
+            bail!("Unexpected NAT-PMP response: {response:?}");
+        }
+
+        return match u16::from_be_bytes([response[2], response[3]]) {
+            0 => Ok(response.to_vec()),
+            1 => Err(eyre!("NAT-PMP gateway doesn't support version 0")),
+            2 => Err(eyre!("NAT-PMP gateway refused the request")),
+            3 => Err(eyre!("NAT-PMP gateway isn't connected to the internet")),
+            4 => Err(eyre!("NAT-PMP gateway is out of resources")),
+            code => Err(eyre!("NAT-PMP gateway returned result code {code}")),
+        };
+    }
+
+    bail!("NAT-PMP gateway {gateway} didn't respond")
+}
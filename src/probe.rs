@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use clap::ValueEnum;
+
+use crate::packet::{self, World};
+
+/// How long to wait for a reaction to each probe before sending the next.
+pub const PROBE_WINDOW: Duration = Duration::from_secs(2);
+
+/// Unusual but legal packets to send once a client has spawned in,
+/// real clients handle all of these but most bots won't react the same way.
+pub fn probes(world: &World) -> [(&'static str, Bytes); 4] {
+    [
+        // loading screen text while already in game, shouldn't get any reaction
+        (
+            "StatusText",
+            packet::status_text(0, "Checking world integrity"),
+        ),
+        // the same world info again
+        ("WorldInfo", packet::world_info(world)),
+        // world info but it's suddenly night
+        ("WorldInfoNight", packet::world_info_at(world, 0, false)),
+        // real clients resend all their player info in response
+        (
+            "ContinueConnecting",
+            Bytes::from_static(b"\x05\x00\x03\0\0"),
+        ),
+    ]
+}
+
+/// Point in the connection to send the malformed packet at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MalformedPoint {
+    /// Just after replying to the connection request.
+    Connect,
+    /// Once the player's name & uuid have been received.
+    Info,
+    /// Once the client's spawned in, only when observing.
+    Spawn,
+}
+
+/// What a client did after being sent a malformed packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reaction {
+    Disconnected,
+    Ignored,
+    /// Sent back these packet ids.
+    Answered(Vec<u8>),
+}
+
+impl Reaction {
+    /// Compact summary for attributes & sinks.
+    /// (one of "disconnected", "ignored" or "answered:id,id")
+    pub fn summary(&self) -> String {
+        match self {
+            Reaction::Disconnected => "disconnected".to_owned(),
+            Reaction::Ignored => "ignored".to_owned(),
+            Reaction::Answered(ids) => format!("answered:{}", hex_ids(ids)),
+        }
+    }
+}
+
+fn hex_ids(ids: &[u8]) -> String {
+    ids.iter()
+        .map(|id| format!("{id:02x}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Packet ids received in response to each probe.
+#[derive(Debug, Default)]
+pub struct ProbeResults(Vec<(&'static str, Vec<u8>)>);
+
+impl ProbeResults {
+    pub fn push(&mut self, probe: &'static str, responses: Vec<u8>) {
+        self.0.push((probe, responses));
+    }
+
+    /// Compact summary for attributes & sinks.
+    /// (in the format of "probe=id,id;probe=")
+    pub fn summary(&self) -> String {
+        self.0
+            .iter()
+            .map(|(probe, responses)| format!("{probe}={}", hex_ids(responses)))
+            .collect::<Vec<_>>()
+            .join(";")
+    }
+}
@@ -0,0 +1,105 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use opentelemetry::{
+    trace::{Link, SamplingDecision, SamplingResult, SpanKind, TraceContextExt, TraceId},
+    Context, KeyValue,
+};
+use opentelemetry_sdk::trace::{Sampler, ShouldSample};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+const WINDOW: Duration = Duration::from_secs(60 * 60);
+
+// past this many addresses, ones outside the window are forgotten
+const TRACKED_ADDRESSES: usize = 10_000;
+
+/// Whether a session should be exported, attached to its span's parent context.
+#[derive(Debug, Clone, Copy)]
+struct SampleSession(bool);
+
+/// Samples sessions by the decision attached to their context,
+/// anything else is always sampled.
+#[derive(Debug, Clone)]
+pub struct SessionSampler;
+
+impl ShouldSample for SessionSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        trace_id: TraceId,
+        name: &str,
+        span_kind: &SpanKind,
+        attributes: &[KeyValue],
+        links: &[Link],
+    ) -> SamplingResult {
+        let Some(SampleSession(sample)) = parent_context.and_then(|cx| cx.get()) else {
+            return Sampler::ParentBased(Box::new(Sampler::AlwaysOn)).should_sample(
+                parent_context,
+                trace_id,
+                name,
+                span_kind,
+                attributes,
+                links,
+            );
+        };
+
+        SamplingResult {
+            decision: if *sample {
+                SamplingDecision::RecordAndSample
+            } else {
+                SamplingDecision::Drop
+            },
+            attributes: Vec::new(),
+            trace_state: parent_context
+                .map(|cx| cx.span().span_context().trace_state().clone())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Picks which sessions get exported, always the first few from each address
+/// every hour, then only a portion of the rest.
+pub struct IpSampler {
+    first: u32,
+    ratio: f64,
+    /// Start of each address's current window & sessions seen in it.
+    windows: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl IpSampler {
+    pub fn new(first: u32, ratio: f64) -> Self {
+        Self {
+            first,
+            ratio,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Decide whether the session `span` belongs to should be exported.
+    pub fn sample(&self, span: &Span, ip: IpAddr) {
+        let sample = self.count(ip) <= self.first || fastrand::f64() < self.ratio;
+        span.set_parent(Context::new().with_value(SampleSession(sample)));
+    }
+
+    /// Count a session from `ip`, returning how many there's been this window.
+    fn count(&self, ip: IpAddr) -> u32 {
+        let mut windows = self.windows.lock().unwrap();
+
+        if windows.len() >= TRACKED_ADDRESSES && !windows.contains_key(&ip) {
+            windows.retain(|_, (started, _)| started.elapsed() < WINDOW);
+        }
+
+        let window = windows.entry(ip).or_insert((Instant::now(), 0));
+        if window.0.elapsed() >= WINDOW {
+            *window = (Instant::now(), 0);
+        }
+
+        window.1 += 1;
+        window.1
+    }
+}
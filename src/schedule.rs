@@ -0,0 +1,253 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+
+use crate::{Args, Engagement};
+
+// todo: need to tune this
+const PASSWORD_TIMEOUT: Duration = Duration::from_secs(30);
+
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+// every day of the week, one bit each starting with monday
+const EVERY_DAY: u8 = 0b111_1111;
+
+/// Hours of the day where the honeypot should act differently,
+/// in the schedule's offset from UTC.
+#[derive(Debug, Clone)]
+pub struct ScheduleWindow {
+    /// Days the window starts on, one bit each starting with monday.
+    days: u8,
+    start: u8,
+    end: u8,
+    change: ScheduledChange,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ScheduledChange {
+    /// Kick everyone with "server is full".
+    Full,
+    PasswordChance(f32),
+    Engagement(Engagement),
+}
+
+/// Parse a schedule window in the format of "[days ]start-end=change".
+///
+/// `days` is a list of days or ranges of them (e.g. "mon-fri" or "sat,sun"), every day if
+/// left out. `start` & `end` are hours from 0 to 24, windows can wrap past midnight
+/// (e.g. "22-6") in which case the days are the ones the window starts on,
+/// the change being one of "full", "password:chance" or "engagement:level".
+pub fn parse_schedule_window(value: &str) -> Result<ScheduleWindow, String> {
+    const FORMAT: &str = "expected the format of \"[days ]start-end=change\"";
+
+    let (window, change) = value.split_once('=').ok_or(FORMAT)?;
+    let (days, hours) = match window.trim().split_once(' ') {
+        Some((days, hours)) => (parse_days(days)?, hours),
+        None => (EVERY_DAY, window),
+    };
+    let (start, end) = hours.split_once('-').ok_or(FORMAT)?;
+
+    let hour = |hour: &str| match hour.trim().parse::<u8>() {
+        Ok(hour) if hour <= 24 => Ok(hour),
+        _ => Err(format!("invalid hour {hour:?}, expected 0 to 24")),
+    };
+
+    let change = match change.split_once(':') {
+        None if change == "full" => ScheduledChange::Full,
+        Some(("password", chance)) => ScheduledChange::PasswordChance(
+            chance
+                .parse()
+                .map_err(|_| format!("invalid password chance {chance:?}"))?,
+        ),
+        Some(("engagement", engagement)) => {
+            ScheduledChange::Engagement(Engagement::from_str(engagement, true)?)
+        }
+        _ => {
+            return Err(format!(
+                "unknown change {change:?}, expected \"full\", \"password:chance\" or \"engagement:level\""
+            ))
+        }
+    };
+
+    Ok(ScheduleWindow {
+        days,
+        start: hour(start)?,
+        end: hour(end)?,
+        change,
+    })
+}
+
+/// Parse a list of days or ranges of them, like "mon-wed,fri", into one bit each.
+fn parse_days(value: &str) -> Result<u8, String> {
+    let day = |day: &str| {
+        DAYS.iter()
+            .position(|name| day.trim().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("invalid day {day:?}, expected one of {}", DAYS.join(", ")))
+    };
+
+    let mut days = 0;
+    for range in value.split(',') {
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (day(start)?, day(end)?),
+            None => (day(range)?, day(range)?),
+        };
+
+        // ranges can wrap past sunday (e.g. "fri-mon")
+        let mut current = start;
+        loop {
+            days |= 1 << current;
+            if current == end {
+                break;
+            }
+            current = (current + 1) % DAYS.len();
+        }
+    }
+
+    Ok(days)
+}
+
+/// Parse an offset from UTC in the format of "+hh[:mm]" or "-hh[:mm]" into seconds.
+pub fn parse_offset(value: &str) -> Result<i64, String> {
+    const FORMAT: &str = "expected the format of \"+hh:mm\" or \"-hh:mm\"";
+
+    let value = value.trim();
+    let (sign, offset) = if let Some(offset) = value.strip_prefix('+') {
+        (1, offset)
+    } else if let Some(offset) = value.strip_prefix('-') {
+        (-1, offset)
+    } else {
+        return Err(FORMAT.to_owned());
+    };
+    let (hours, minutes) = offset.split_once(':').unwrap_or((offset, "0"));
+
+    match (hours.parse::<i64>(), minutes.parse::<i64>()) {
+        (Ok(hours @ 0..=14), Ok(minutes @ 0..=59)) => Ok(sign * (hours * 60 + minutes) * 60),
+        _ => Err(FORMAT.to_owned()),
+    }
+}
+
+impl ScheduleWindow {
+    /// Whether `hour` of `day` (0 being monday) is in the window.
+    fn contains(&self, day: usize, hour: u8) -> bool {
+        let starts_on = |day: usize| self.days & (1 << day) != 0;
+        let yesterday = (day + DAYS.len() - 1) % DAYS.len();
+
+        if self.start <= self.end {
+            starts_on(day) && (self.start..self.end).contains(&hour)
+        } else {
+            // the early hours belong to the window that started the day before
+            (starts_on(day) && hour >= self.start) || (starts_on(yesterday) && hour < self.end)
+        }
+    }
+}
+
+/// How the honeypot should treat a connection, after applying the schedule.
+#[derive(Debug, Clone, Copy)]
+pub struct Persona {
+    pub full: bool,
+    pub password_chance: f32,
+    pub engagement: Engagement,
+    /// Shedding load, sessions are kept short & never engaged with.
+    pub shedding: bool,
+    /// How long to wait on the client between packets.
+    pub idle_timeout: Duration,
+    /// How long to wait on the client to send a password.
+    pub password_timeout: Duration,
+}
+
+impl Persona {
+    /// Persona for a connection made right now,
+    /// when windows overlap the later ones win & shedding load beats everything.
+    pub fn current(args: &Args, shedding: bool) -> Self {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64
+            + args.schedule_offset;
+        let hour = (seconds.div_euclid(60 * 60) % 24) as u8;
+        // the epoch was a thursday
+        let day = (seconds.div_euclid(60 * 60 * 24) + 3).rem_euclid(7) as usize;
+
+        let mut persona = Self {
+            full: false,
+            password_chance: args.password_chance,
+            engagement: args.engagement,
+            shedding,
+            idle_timeout: crate::IDLE_TIMEOUT,
+            password_timeout: PASSWORD_TIMEOUT,
+        };
+
+        for window in args
+            .schedule
+            .iter()
+            .filter(|window| window.contains(day, hour))
+        {
+            match window.change {
+                ScheduledChange::Full => persona.full = true,
+                ScheduledChange::PasswordChance(chance) => persona.password_chance = chance,
+                ScheduledChange::Engagement(engagement) => persona.engagement = engagement,
+            }
+        }
+
+        if shedding {
+            persona.engagement = Engagement::None;
+            persona.idle_timeout = args.load.shedding_idle_timeout();
+            persona.password_timeout = args.load.shedding_password_timeout();
+        }
+
+        persona
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_without_days_is_every_day() {
+        let window = parse_schedule_window("9-17=full").unwrap();
+
+        assert_eq!(window.days, EVERY_DAY);
+        assert!(window.contains(0, 9));
+        assert!(window.contains(6, 16));
+        assert!(!window.contains(3, 17));
+    }
+
+    #[test]
+    fn window_with_days() {
+        let window = parse_schedule_window("mon-wed,fri 9-17=password:0.5").unwrap();
+
+        assert_eq!(window.days, 0b001_0111);
+        assert!(window.contains(2, 12));
+        assert!(!window.contains(3, 12));
+        assert!(window.contains(4, 12));
+    }
+
+    #[test]
+    fn overnight_window_belongs_to_the_day_it_starts() {
+        let window = parse_schedule_window("fri 22-6=full").unwrap();
+
+        assert!(window.contains(4, 23));
+        assert!(window.contains(5, 5));
+        assert!(!window.contains(5, 23));
+        assert!(!window.contains(4, 5));
+    }
+
+    #[test]
+    fn day_ranges_wrap() {
+        assert_eq!(parse_days("sat-mon").unwrap(), 0b110_0001);
+        assert_eq!(parse_days("Sun").unwrap(), 0b100_0000);
+        assert!(parse_days("someday").is_err());
+        assert!(parse_schedule_window("mon,funday 1-2=full").is_err());
+    }
+
+    #[test]
+    fn offsets() {
+        assert_eq!(parse_offset("+00:00"), Ok(0));
+        assert_eq!(parse_offset("+10"), Ok(10 * 60 * 60));
+        assert_eq!(parse_offset("-03:30"), Ok(-(3 * 60 + 30) * 60));
+        assert!(parse_offset("10").is_err());
+        assert!(parse_offset("+15:00").is_err());
+        assert!(parse_offset("+01:60").is_err());
+        assert!(parse_offset("").is_err());
+    }
+}
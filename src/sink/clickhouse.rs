@@ -0,0 +1,198 @@
+use std::{sync::Arc, time::Duration};
+
+use clap::Parser;
+
+use super::{Batching, SessionEvent, Sink};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Sessions are inserted as JSONEachRow, into a table along the lines of:
+///
+/// ```sql
+/// CREATE TABLE bottled_honey.sessions (
+///     timestamp DateTime64(6, 'UTC'),
+///     session_id String,
+///     peer_ip IPv6,
+///     peer_port UInt16,
+///     service LowCardinality(String),
+///     world_name LowCardinality(Nullable(String)),
+///     duration Float64,
+///     outcome LowCardinality(String),
+///     error Nullable(String),
+///     bytes_sent Nullable(UInt64),
+///     bytes_received Nullable(UInt64),
+///     version LowCardinality(Nullable(String)),
+///     tmodloader Bool,
+///     mods Array(String),
+///     requested_mod Nullable(String),
+///     connect_extension Nullable(String),
+///     password Nullable(String),
+///     player_name Nullable(String),
+///     player_uuid Nullable(String),
+///     difficulty LowCardinality(Nullable(String)),
+///     player_flags Array(LowCardinality(String)),
+///     player_extension Nullable(String),
+///     fingerprint Nullable(String),
+///     spawn_x Nullable(Int16),
+///     spawn_y Nullable(Int16),
+///     packets Nullable(UInt32),
+///     chat Array(String),
+///     challenge_passed Nullable(Bool),
+///     probe_responses Nullable(String),
+///     malformed_reaction LowCardinality(Nullable(String)),
+///     logins Array(Tuple(username String, password String)),
+///     greynoise_classification LowCardinality(Nullable(String)),
+///     greynoise_name Nullable(String),
+///     greynoise_noise Nullable(Bool),
+///     greynoise_riot Nullable(Bool),
+///     shodan_ports Array(UInt16),
+///     shodan_tags Array(LowCardinality(String)),
+///     shodan_cpes Array(String),
+///     shodan_vulns Array(String),
+///     shodan_hostnames Array(String),
+///     labels Map(LowCardinality(String), String)
+/// )
+/// ENGINE = MergeTree
+/// ORDER BY (timestamp, session_id)
+/// ```
+#[derive(Debug, Parser)]
+pub struct ClickHouseArgs {
+    /// ClickHouse url.
+    ///
+    /// ClickHouse HTTP interface to insert sessions into.
+    /// (e.g. http://localhost:8123)
+    #[arg(
+        id = "clickhouse_url",
+        value_name = "URL",
+        env = "CLICKHOUSE_URL",
+        long = "clickhouse-url"
+    )]
+    url: Option<String>,
+
+    /// ClickHouse table.
+    ///
+    /// Table sessions are inserted into.
+    #[arg(
+        id = "clickhouse_table",
+        value_name = "TABLE",
+        env = "CLICKHOUSE_TABLE",
+        long = "clickhouse-table",
+        default_value = "bottled_honey.sessions"
+    )]
+    table: String,
+
+    /// ClickHouse user.
+    #[arg(
+        id = "clickhouse_user",
+        value_name = "USER",
+        env = "CLICKHOUSE_USER",
+        long = "clickhouse-user"
+    )]
+    user: Option<String>,
+
+    /// ClickHouse password.
+    #[arg(
+        id = "clickhouse_password",
+        value_name = "PASSWORD",
+        env = "CLICKHOUSE_PASSWORD",
+        long = "clickhouse-password"
+    )]
+    password: Option<String>,
+
+    /// ClickHouse batch size.
+    ///
+    /// Maximum number of sessions inserted at once.
+    #[arg(
+        id = "clickhouse_batch_size",
+        value_name = "BATCH_SIZE",
+        env = "CLICKHOUSE_BATCH_SIZE",
+        long = "clickhouse-batch-size",
+        default_value_t = 1000
+    )]
+    batch_size: usize,
+
+    /// ClickHouse flush interval.
+    ///
+    /// Longest to wait for a batch to fill up before inserting it anyway.
+    /// (in seconds)
+    #[arg(
+        id = "clickhouse_flush_interval",
+        value_name = "FLUSH_INTERVAL",
+        env = "CLICKHOUSE_FLUSH_INTERVAL",
+        long = "clickhouse-flush-interval",
+        default_value_t = 5
+    )]
+    flush_interval: u64,
+}
+
+pub struct ClickHouse {
+    client: reqwest::Client,
+    url: String,
+    query: String,
+    user: Option<String>,
+    password: Option<String>,
+}
+
+impl ClickHouse {
+    pub fn from_args(args: &ClickHouseArgs) -> Option<(Self, Batching)> {
+        let url = args.url.as_ref()?;
+
+        let clickhouse = Self {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("failed to build http client"),
+            url: url.clone(),
+            query: format!("INSERT INTO {} FORMAT JSONEachRow", args.table),
+            user: args.user.clone(),
+            password: args.password.clone(),
+        };
+
+        let batching = Batching {
+            size: args.batch_size.max(1),
+            interval: Duration::from_secs(args.flush_interval),
+        };
+
+        Some((clickhouse, batching))
+    }
+}
+
+impl Sink for ClickHouse {
+    fn name(&self) -> &'static str {
+        "clickhouse"
+    }
+
+    async fn write(&self, batch: &[Arc<SessionEvent>]) -> Result<(), String> {
+        let mut body = Vec::new();
+        for event in batch {
+            serde_json::to_writer(&mut body, &**event).map_err(|error| error.to_string())?;
+            body.push(b'\n');
+        }
+
+        let mut request = self
+            .client
+            .post(&self.url)
+            // rfc3339 timestamps need the best effort parser
+            .query(&[
+                ("query", self.query.as_str()),
+                ("date_time_input_format", "best_effort"),
+            ])
+            .body(body);
+
+        if let Some(user) = &self.user {
+            request = request.header("X-ClickHouse-User", user);
+        }
+        if let Some(password) = &self.password {
+            request = request.header("X-ClickHouse-Key", password);
+        }
+
+        let response = request.send().await.map_err(|error| error.to_string())?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("{status}: {}", body.trim()));
+        }
+
+        Ok(())
+    }
+}
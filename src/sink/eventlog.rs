@@ -0,0 +1,240 @@
+use clap::Parser;
+
+use super::Batching;
+
+#[derive(Debug, Parser)]
+pub struct EventLogArgs {
+    /// Windows Event Log source.
+    ///
+    /// Write sessions to the Windows Application log under this source, each as a json
+    /// event message, for SIEM collectors already reading the Event Log. Registering the
+    /// source needs to be run as administrator once, events are still written without it
+    /// but show up with a "description cannot be found" note. (Windows only)
+    #[arg(env = "EVENTLOG_SOURCE", long = "eventlog-source")]
+    source: Option<String>,
+}
+
+/// Can't be constructed anywhere but Windows,
+/// only here so sinks are set up the same on every platform.
+#[cfg(not(windows))]
+pub enum EventLog {}
+
+#[cfg(not(windows))]
+impl EventLog {
+    pub fn from_args(args: &EventLogArgs) -> color_eyre::Result<Option<(Self, Batching)>> {
+        if args.source.is_some() {
+            color_eyre::eyre::bail!("The Windows Event Log sink is only available on Windows");
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(not(windows))]
+impl super::Sink for EventLog {
+    fn name(&self) -> &'static str {
+        match *self {}
+    }
+
+    async fn write(&self, _: &[std::sync::Arc<super::SessionEvent>]) -> Result<(), String> {
+        match *self {}
+    }
+}
+
+#[cfg(windows)]
+pub use windows::EventLog;
+
+#[cfg(windows)]
+mod windows {
+    use std::{ptr, sync::Arc, time::Duration};
+
+    use color_eyre::eyre::eyre;
+    use tracing::{info, warn};
+    use windows_sys::Win32::{
+        Foundation::{ERROR_SUCCESS, HANDLE},
+        System::{
+            EventLog::{
+                DeregisterEventSource, RegisterEventSourceW, ReportEventW,
+                EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE,
+            },
+            Registry::{
+                RegCloseKey, RegCreateKeyExW, RegSetValueExW, HKEY, HKEY_LOCAL_MACHINE,
+                KEY_SET_VALUE, REG_DWORD, REG_EXPAND_SZ, REG_OPTION_NON_VOLATILE,
+            },
+        },
+    };
+
+    use super::{super::SessionEvent, super::Sink, Batching, EventLogArgs};
+
+    const SOURCES_KEY: &str = r"SYSTEM\CurrentControlSet\Services\EventLog\Application";
+
+    // ships with .NET & has every message id as just "%1", the same trick .NET's own
+    // EventLog uses so sources don't need their own message dll
+    const MESSAGE_FILE: &str =
+        r"%SystemRoot%\Microsoft.NET\Framework64\v4.0.30319\EventLogMessages.dll";
+
+    // error, warning & information
+    const TYPES_SUPPORTED: u32 = 0x7;
+
+    // longest string a single event can hold, in utf-16 units
+    const MAX_MESSAGE_LENGTH: usize = 31_839;
+
+    // event ids for each service, so collectors can filter without parsing the message
+    const TERRARIA_EVENT_ID: u32 = 1;
+    const CONSOLE_EVENT_ID: u32 = 2;
+
+    pub struct EventLog {
+        handle: HANDLE,
+    }
+
+    impl EventLog {
+        pub fn from_args(args: &EventLogArgs) -> color_eyre::Result<Option<(Self, Batching)>> {
+            let Some(source) = &args.source else {
+                return Ok(None);
+            };
+
+            match register(source) {
+                Ok(()) => info!("Registered Event Log source {source:?}"),
+                Err(error) => warn!("Failed to register Event Log source {source:?}: {error}"),
+            }
+
+            let name = wide(source);
+            // SAFETY: name is a nul terminated utf-16 string that outlives the call
+            let handle = unsafe { RegisterEventSourceW(ptr::null(), name.as_ptr()) };
+            if handle == 0 {
+                return Err(eyre!(
+                    "Failed to open Event Log source {source:?}: {}",
+                    std::io::Error::last_os_error()
+                ));
+            }
+
+            // writes are local & cheap, no need to make it configurable
+            let batching = Batching {
+                size: 100,
+                interval: Duration::from_secs(1),
+            };
+
+            Ok(Some((Self { handle }, batching)))
+        }
+    }
+
+    impl Drop for EventLog {
+        fn drop(&mut self) {
+            // SAFETY: the handle came from RegisterEventSourceW & isn't used after this
+            unsafe { DeregisterEventSource(self.handle) };
+        }
+    }
+
+    impl Sink for EventLog {
+        fn name(&self) -> &'static str {
+            "eventlog"
+        }
+
+        async fn write(&self, batch: &[Arc<SessionEvent>]) -> Result<(), String> {
+            for event in batch {
+                let json = serde_json::to_string(&**event).map_err(|error| error.to_string())?;
+                let mut message = json.encode_utf16().collect::<Vec<_>>();
+                message.truncate(MAX_MESSAGE_LENGTH);
+                message.push(0);
+
+                let kind = if event.error.is_some() {
+                    EVENTLOG_WARNING_TYPE
+                } else {
+                    EVENTLOG_INFORMATION_TYPE
+                };
+                let id = match event.service {
+                    "console" => CONSOLE_EVENT_ID,
+                    _ => TERRARIA_EVENT_ID,
+                };
+
+                let strings = [message.as_ptr()];
+                // SAFETY: the handle is open for as long as self is around
+                // & every string is nul terminated
+                let reported = unsafe {
+                    ReportEventW(
+                        self.handle,
+                        kind,
+                        0,
+                        id,
+                        ptr::null_mut(),
+                        strings.len() as u16,
+                        0,
+                        strings.as_ptr(),
+                        ptr::null(),
+                    )
+                };
+
+                if reported == 0 {
+                    return Err(std::io::Error::last_os_error().to_string());
+                }
+            }
+
+            Ok(())
+        }
+    }
+
+    /// Add the source to the registry so its events are displayed properly,
+    /// needs administrator but only has to be done once.
+    fn register(source: &str) -> std::io::Result<()> {
+        let path = wide(&format!(r"{SOURCES_KEY}\{source}"));
+        let mut key: HKEY = 0;
+
+        // SAFETY: every pointer is either null or valid for the duration of the call
+        let status = unsafe {
+            RegCreateKeyExW(
+                HKEY_LOCAL_MACHINE,
+                path.as_ptr(),
+                0,
+                ptr::null(),
+                REG_OPTION_NON_VOLATILE,
+                KEY_SET_VALUE,
+                ptr::null(),
+                &mut key,
+                ptr::null_mut(),
+            )
+        };
+        if status != ERROR_SUCCESS {
+            return Err(std::io::Error::from_raw_os_error(status as i32));
+        }
+
+        let message_file = wide(MESSAGE_FILE);
+        let values: [(&str, u32, &[u8]); 2] = [
+            ("EventMessageFile", REG_EXPAND_SZ, as_bytes(&message_file)),
+            ("TypesSupported", REG_DWORD, &TYPES_SUPPORTED.to_le_bytes()),
+        ];
+
+        let mut result = Ok(());
+        for (name, kind, data) in values {
+            let name = wide(name);
+            // SAFETY: the key is open & data is valid for its length
+            let status = unsafe {
+                RegSetValueExW(
+                    key,
+                    name.as_ptr(),
+                    0,
+                    kind,
+                    data.as_ptr(),
+                    data.len() as u32,
+                )
+            };
+            if status != ERROR_SUCCESS {
+                result = Err(std::io::Error::from_raw_os_error(status as i32));
+                break;
+            }
+        }
+
+        // SAFETY: the key was opened above & isn't used after this
+        unsafe { RegCloseKey(key) };
+        result
+    }
+
+    /// Nul terminated utf-16, as the wide api wants.
+    fn wide(string: &str) -> Vec<u16> {
+        string.encode_utf16().chain([0]).collect()
+    }
+
+    fn as_bytes(data: &[u16]) -> &[u8] {
+        // SAFETY: u8 has no alignment requirement & the length covers the same bytes
+        unsafe { std::slice::from_raw_parts(data.as_ptr().cast(), std::mem::size_of_val(data)) }
+    }
+}
@@ -0,0 +1,421 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::{BufMut, BytesMut};
+use clap::Parser;
+use color_eyre::eyre::{Context, Result};
+use tokio::net::UdpSocket;
+use tracing::debug;
+
+use super::{Batching, SessionEvent, Sink};
+
+const VERSION: u16 = 10;
+const TEMPLATE_SET_ID: u16 = 2;
+const TEMPLATE_ID: u16 = 256;
+const HEADER_LENGTH: usize = 16;
+
+// keeps messages inside a single packet on pretty much any network
+const MAX_MESSAGE_LENGTH: usize = 1400;
+
+// rfc 5103 reverse elements are the usual element under this enterprise
+const REVERSE_ENTERPRISE: u32 = 29305;
+
+const TCP: u8 = 6;
+// variable length strings longer than this are cut down, keeps every record well inside a message
+const MAX_STRING_LENGTH: usize = 200;
+
+/// Field specifiers of the one template, (element id, length, enterprise).
+/// A length of 0xffff is variable length.
+const FIELDS: [(u16, u16, Option<Enterprise>); 17] = [
+    // sourceIPv4Address, destinationIPv4Address
+    (8, 4, None),
+    (12, 4, None),
+    // sourceTransportPort, destinationTransportPort, protocolIdentifier
+    (7, 2, None),
+    (11, 2, None),
+    (4, 1, None),
+    // flowStartMilliseconds, flowEndMilliseconds
+    (152, 8, None),
+    (153, 8, None),
+    // octetDeltaCount, what the client sent
+    (1, 8, None),
+    // reverseOctetDeltaCount, what the honeypot sent back
+    (1, 8, Some(Enterprise::Reverse)),
+    // our own elements
+    (1, 0xffff, Some(Enterprise::Own)),
+    (2, 0xffff, Some(Enterprise::Own)),
+    (3, 0xffff, Some(Enterprise::Own)),
+    (4, 0xffff, Some(Enterprise::Own)),
+    (5, 0xffff, Some(Enterprise::Own)),
+    (6, 0xffff, Some(Enterprise::Own)),
+    (7, 0xffff, Some(Enterprise::Own)),
+    (8, 0xffff, Some(Enterprise::Own)),
+];
+
+#[derive(Clone, Copy)]
+enum Enterprise {
+    Reverse,
+    Own,
+}
+
+#[derive(Debug, Parser)]
+pub struct IpfixArgs {
+    /// IPFIX collector.
+    ///
+    /// Export a flow record for each session to this collector over UDP. The outcome,
+    /// client fingerprint, service, session id, client version, labels, GreyNoise
+    /// classification & Shodan tags are sent as enterprise specific elements 1 to 8.
+    /// (expected format: ip:port)
+    #[arg(
+        id = "ipfix_collector",
+        value_name = "COLLECTOR",
+        env = "IPFIX_COLLECTOR",
+        long = "ipfix-collector"
+    )]
+    collector: Option<SocketAddr>,
+
+    /// IPFIX enterprise number.
+    ///
+    /// Private enterprise number the custom elements are exported under,
+    /// defaults to the one reserved for documentation so set your own if you have one.
+    #[arg(
+        id = "ipfix_enterprise",
+        value_name = "ENTERPRISE",
+        env = "IPFIX_ENTERPRISE",
+        long = "ipfix-enterprise",
+        default_value_t = 32473
+    )]
+    enterprise: u32,
+
+    /// IPFIX observation domain.
+    #[arg(
+        id = "ipfix_observation_domain",
+        value_name = "OBSERVATION_DOMAIN",
+        env = "IPFIX_OBSERVATION_DOMAIN",
+        long = "ipfix-observation-domain",
+        default_value_t = 0
+    )]
+    observation_domain: u32,
+
+    /// IPFIX flush interval.
+    ///
+    /// Longest to wait for more sessions before exporting anyway.
+    /// (in seconds)
+    #[arg(
+        id = "ipfix_flush_interval",
+        value_name = "FLUSH_INTERVAL",
+        env = "IPFIX_FLUSH_INTERVAL",
+        long = "ipfix-flush-interval",
+        default_value_t = 5
+    )]
+    flush_interval: u64,
+}
+
+pub struct Ipfix {
+    socket: UdpSocket,
+    collector: SocketAddr,
+    enterprise: u32,
+    observation_domain: u32,
+    /// Data records exported so far, as the header's sequence number wants.
+    sequence: AtomicU32,
+}
+
+impl Ipfix {
+    pub fn from_args(args: &IpfixArgs) -> Result<Option<(Self, Batching)>> {
+        let Some(collector) = args.collector else {
+            return Ok(None);
+        };
+
+        let bind: SocketAddr = match collector {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (std::net::Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = std::net::UdpSocket::bind(bind)
+            .and_then(|socket| {
+                socket.set_nonblocking(true)?;
+                UdpSocket::from_std(socket)
+            })
+            .wrap_err("Failed to bind IPFIX socket")?;
+
+        let ipfix = Self {
+            socket,
+            collector,
+            enterprise: args.enterprise,
+            observation_domain: args.observation_domain,
+            sequence: AtomicU32::new(0),
+        };
+
+        let batching = Batching {
+            size: 100,
+            interval: Duration::from_secs(args.flush_interval),
+        };
+
+        Ok(Some((ipfix, batching)))
+    }
+
+    /// Start a message with the template, it's sent every time since udp collectors
+    /// could have restarted & forgotten it since the last one.
+    fn message(&self) -> BytesMut {
+        let mut message = BytesMut::with_capacity(MAX_MESSAGE_LENGTH);
+        // header's filled in once the message is finished
+        message.put_bytes(0, HEADER_LENGTH);
+
+        let set = message.len();
+        message.put_u16(TEMPLATE_SET_ID);
+        message.put_u16(0);
+        message.put_u16(TEMPLATE_ID);
+        message.put_u16(FIELDS.len() as u16);
+        for (id, length, enterprise) in FIELDS {
+            let enterprise = enterprise.map(|enterprise| match enterprise {
+                Enterprise::Reverse => REVERSE_ENTERPRISE,
+                Enterprise::Own => self.enterprise,
+            });
+
+            // the top bit marks an enterprise number following the length
+            message.put_u16(if enterprise.is_some() {
+                id | 0x8000
+            } else {
+                id
+            });
+            message.put_u16(length);
+            if let Some(enterprise) = enterprise {
+                message.put_u32(enterprise);
+            }
+        }
+        set_length(&mut message, set);
+
+        message
+    }
+
+    async fn send(&self, mut message: BytesMut, records: u32) -> Result<(), String> {
+        let length = message.len() as u16;
+        let export_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as u32;
+        let sequence = self.sequence.fetch_add(records, Ordering::Relaxed);
+
+        let mut header = &mut message[..HEADER_LENGTH];
+        header.put_u16(VERSION);
+        header.put_u16(length);
+        header.put_u32(export_time);
+        header.put_u32(sequence);
+        header.put_u32(self.observation_domain);
+
+        self.socket
+            .send_to(&message, self.collector)
+            .await
+            .map_err(|error| error.to_string())?;
+
+        Ok(())
+    }
+}
+
+impl Sink for Ipfix {
+    fn name(&self) -> &'static str {
+        "ipfix"
+    }
+
+    async fn write(&self, batch: &[Arc<SessionEvent>]) -> Result<(), String> {
+        let mut message = self.message();
+        let mut set = None;
+        let mut records = 0;
+
+        for event in batch {
+            let Some(record) = record(event) else {
+                continue;
+            };
+
+            // start a new message once this one's full
+            if message.len() + record.len() + 4 > MAX_MESSAGE_LENGTH && records > 0 {
+                set = None;
+                self.send(message, records).await?;
+
+                message = self.message();
+                records = 0;
+            }
+
+            let start = *set.get_or_insert_with(|| {
+                let start = message.len();
+                message.put_u16(TEMPLATE_ID);
+                message.put_u16(0);
+                start
+            });
+            message.put(record);
+            records += 1;
+            set_length(&mut message, start);
+        }
+
+        if records > 0 {
+            self.send(message, records).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Data record for a session, none if it isn't an ipv4 flow.
+fn record(event: &SessionEvent) -> Option<BytesMut> {
+    let (IpAddr::V4(peer_ip), IpAddr::V4(local_ip)) =
+        (event.peer_ip.parse().ok()?, event.local_addr.ip())
+    else {
+        debug!(
+            "Skipping IPFIX record for non ipv4 session {}",
+            event.session_id
+        );
+        return None;
+    };
+
+    let started = event
+        .started
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let ended = started + (event.duration * 1000.0) as u64;
+
+    let mut record = BytesMut::new();
+    record.put_slice(&peer_ip.octets());
+    record.put_slice(&local_ip.octets());
+    record.put_u16(event.peer_port);
+    record.put_u16(event.local_addr.port());
+    record.put_u8(TCP);
+    record.put_u64(started);
+    record.put_u64(ended);
+    record.put_u64(event.bytes_received.unwrap_or_default());
+    record.put_u64(event.bytes_sent.unwrap_or_default());
+    put_string(&mut record, event.outcome);
+    put_string(
+        &mut record,
+        event.fingerprint.as_deref().unwrap_or_default(),
+    );
+    put_string(&mut record, event.service);
+    put_string(&mut record, &event.session_id);
+    put_string(&mut record, event.version.as_deref().unwrap_or_default());
+    put_string(
+        &mut record,
+        &event
+            .labels
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect::<Vec<_>>()
+            .join(","),
+    );
+    put_string(
+        &mut record,
+        event
+            .greynoise_classification
+            .as_deref()
+            .unwrap_or_default(),
+    );
+    put_string(&mut record, &event.shodan_tags.join(","));
+
+    Some(record)
+}
+
+/// Variable length string, always short enough for the single byte length.
+fn put_string(buffer: &mut BytesMut, string: &str) {
+    let mut end = string.len().min(MAX_STRING_LENGTH);
+    while !string.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    buffer.put_u8(end as u8);
+    buffer.put_slice(&string.as_bytes()[..end]);
+}
+
+/// Fill in the length of the set starting at `start`, now that it's been written.
+fn set_length(message: &mut BytesMut, start: usize) {
+    let length = (message.len() - start) as u16;
+    message[start + 2..start + 4].copy_from_slice(&length.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(peer_addr: &str) -> SessionEvent {
+        let mut event = SessionEvent::console(
+            "0123456789abcdef",
+            peer_addr.parse().unwrap(),
+            "192.0.2.2:7777".parse().unwrap(),
+            UNIX_EPOCH + Duration::from_millis(1_000),
+            Vec::new(),
+            &Ok(()),
+        );
+        event.duration = 2.5;
+        event.bytes_received = Some(10);
+        event.bytes_sent = Some(20);
+        event
+    }
+
+    #[test]
+    fn string_with_length() {
+        let mut buffer = BytesMut::new();
+        put_string(&mut buffer, "abc");
+        put_string(&mut buffer, "");
+
+        assert_eq!(&buffer[..], b"\x03abc\x00");
+    }
+
+    #[test]
+    fn long_strings_are_cut_down() {
+        let mut buffer = BytesMut::new();
+        put_string(&mut buffer, &"a".repeat(300));
+        assert_eq!(buffer[0] as usize, MAX_STRING_LENGTH);
+        assert_eq!(buffer.len(), 1 + MAX_STRING_LENGTH);
+
+        // the cut would land in the middle of an "é"
+        let mut buffer = BytesMut::new();
+        put_string(&mut buffer, &format!("a{}", "é".repeat(150)));
+        assert_eq!(buffer[0] as usize, MAX_STRING_LENGTH - 1);
+        assert!(std::str::from_utf8(&buffer[1..]).is_ok());
+    }
+
+    #[test]
+    fn record_matches_template() {
+        let record = record(&event("192.0.2.1:50000")).unwrap();
+
+        let mut expected = BytesMut::new();
+        expected.put_slice(&[192, 0, 2, 1, 192, 0, 2, 2]);
+        expected.put_u16(50000);
+        expected.put_u16(7777);
+        expected.put_u8(TCP);
+        expected.put_u64(1_000);
+        expected.put_u64(3_500);
+        expected.put_u64(10);
+        expected.put_u64(20);
+        for string in [
+            "completed",
+            "",
+            "console",
+            "0123456789abcdef",
+            "",
+            "",
+            "",
+            "",
+        ] {
+            put_string(&mut expected, string);
+        }
+        assert_eq!(record, expected);
+
+        // every fixed length field, then one length byte for each variable one
+        let fixed = FIELDS.iter().filter(|(_, length, _)| *length != 0xffff);
+        let variable = FIELDS.len() - fixed.clone().count();
+        let fixed = fixed.map(|(_, length, _)| *length as usize).sum::<usize>();
+        assert_eq!(
+            record.len(),
+            fixed + variable + "completedconsole0123456789abcdef".len()
+        );
+    }
+
+    #[test]
+    fn ipv6_sessions_have_no_record() {
+        assert!(record(&event("[2001:db8::1]:50000")).is_none());
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use clap::{Parser, ValueEnum};
+use serde_json::json;
+
+use super::{Batching, SessionEvent, Sink};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Parser)]
+pub struct LokiArgs {
+    /// Loki url.
+    ///
+    /// Loki instance to push sessions to.
+    /// (e.g. http://localhost:3100)
+    #[arg(
+        id = "loki_url",
+        value_name = "URL",
+        env = "LOKI_URL",
+        long = "loki-url"
+    )]
+    url: Option<String>,
+
+    /// Loki labels.
+    ///
+    /// Extra labels added to every stream, like which node the events came from.
+    /// (expects the format of "key=val,key=val")
+    #[arg(
+        id = "loki_labels",
+        value_name = "LABELS",
+        env = "LOKI_LABELS",
+        long = "loki-labels",
+        value_delimiter = ',',
+        value_parser = crate::parse_label
+    )]
+    labels: Vec<(String, String)>,
+
+    /// Loki session labels.
+    ///
+    /// Session fields to use as labels, keep these low cardinality.
+    /// (expects the format of "field,field")
+    #[arg(
+        id = "loki_session_labels",
+        value_name = "SESSION_LABELS",
+        env = "LOKI_SESSION_LABELS",
+        long = "loki-session-labels",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "outcome"
+    )]
+    session_labels: Vec<SessionLabel>,
+
+    /// Loki tenant.
+    ///
+    /// Sent as the X-Scope-OrgID header, for multi-tenant setups.
+    #[arg(
+        id = "loki_tenant",
+        value_name = "TENANT",
+        env = "LOKI_TENANT",
+        long = "loki-tenant"
+    )]
+    tenant: Option<String>,
+
+    /// Loki user.
+    #[arg(
+        id = "loki_user",
+        value_name = "USER",
+        env = "LOKI_USER",
+        long = "loki-user"
+    )]
+    user: Option<String>,
+
+    /// Loki password.
+    #[arg(
+        id = "loki_password",
+        value_name = "PASSWORD",
+        env = "LOKI_PASSWORD",
+        long = "loki-password"
+    )]
+    password: Option<String>,
+
+    /// Loki batch size.
+    ///
+    /// Maximum number of sessions pushed at once.
+    #[arg(
+        id = "loki_batch_size",
+        value_name = "BATCH_SIZE",
+        env = "LOKI_BATCH_SIZE",
+        long = "loki-batch-size",
+        default_value_t = 500
+    )]
+    batch_size: usize,
+
+    /// Loki flush interval.
+    ///
+    /// Longest to wait for a batch to fill up before pushing it anyway.
+    /// (in seconds)
+    #[arg(
+        id = "loki_flush_interval",
+        value_name = "FLUSH_INTERVAL",
+        env = "LOKI_FLUSH_INTERVAL",
+        long = "loki-flush-interval",
+        default_value_t = 5
+    )]
+    flush_interval: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SessionLabel {
+    Outcome,
+    Version,
+    World,
+    Service,
+}
+
+pub struct Loki {
+    client: reqwest::Client,
+    url: String,
+    labels: BTreeMap<String, String>,
+    session_labels: Vec<SessionLabel>,
+    tenant: Option<String>,
+    user: Option<String>,
+    password: Option<String>,
+}
+
+impl Loki {
+    pub fn from_args(args: &LokiArgs) -> Option<(Self, Batching)> {
+        let url = args.url.as_ref()?;
+
+        let mut labels = BTreeMap::from([("job".to_owned(), "bottled_honey".to_owned())]);
+        labels.extend(args.labels.iter().cloned());
+
+        let loki = Self {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("failed to build http client"),
+            url: format!("{}/loki/api/v1/push", url.trim_end_matches('/')),
+            labels,
+            session_labels: args.session_labels.clone(),
+            tenant: args.tenant.clone(),
+            user: args.user.clone(),
+            password: args.password.clone(),
+        };
+
+        let batching = Batching {
+            size: args.batch_size.max(1),
+            interval: Duration::from_secs(args.flush_interval),
+        };
+
+        Some((loki, batching))
+    }
+
+    fn labels(&self, event: &SessionEvent) -> BTreeMap<String, String> {
+        let mut labels = event.labels.clone();
+        labels.extend(self.labels.clone());
+
+        for label in &self.session_labels {
+            let (key, value) = match label {
+                SessionLabel::Outcome => ("outcome", event.outcome),
+                SessionLabel::Version => ("version", event.version.as_deref().unwrap_or("unknown")),
+                SessionLabel::World => ("world", event.world_name.as_deref().unwrap_or("none")),
+                SessionLabel::Service => ("service", event.service),
+            };
+
+            labels.insert(key.to_owned(), value.to_owned());
+        }
+
+        labels
+    }
+}
+
+impl Sink for Loki {
+    fn name(&self) -> &'static str {
+        "loki"
+    }
+
+    async fn write(&self, batch: &[Arc<SessionEvent>]) -> Result<(), String> {
+        // one stream per distinct label set
+        let mut streams: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for event in batch {
+            let timestamp = event
+                .started
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos();
+            let line = serde_json::to_string(&**event).map_err(|error| error.to_string())?;
+
+            streams
+                .entry(self.labels(event))
+                .or_default()
+                .push(json!([timestamp.to_string(), line]));
+        }
+
+        let streams: Vec<_> = streams
+            .into_iter()
+            .map(|(labels, values)| json!({ "stream": labels, "values": values }))
+            .collect();
+
+        let mut request = self
+            .client
+            .post(&self.url)
+            .json(&json!({ "streams": streams }));
+
+        if let Some(tenant) = &self.tenant {
+            request = request.header("X-Scope-OrgID", tenant);
+        }
+        if let Some(user) = &self.user {
+            request = request.basic_auth(user, self.password.as_ref());
+        }
+
+        let response = request.send().await.map_err(|error| error.to_string())?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("{status}: {}", body.trim()));
+        }
+
+        Ok(())
+    }
+}
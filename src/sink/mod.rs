@@ -0,0 +1,501 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use opentelemetry::{
+    metrics::{Counter, Gauge, Histogram},
+    KeyValue,
+};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tracing::{debug, error, warn};
+
+use crate::{
+    client::{ClientInfo, Session},
+    console::Login,
+    enrich::Enriched,
+    error::{self, SessionError},
+    packet,
+    probe::{ProbeResults, Reaction},
+};
+
+mod clickhouse;
+mod eventlog;
+mod ipfix;
+mod loki;
+mod zabbix;
+
+pub use clickhouse::ClickHouseArgs;
+pub use eventlog::EventLogArgs;
+pub use ipfix::IpfixArgs;
+pub use loki::LokiArgs;
+pub use zabbix::{ZabbixAlerts, ZabbixArgs};
+
+// events waiting to be written, past this new events are dropped
+// rather than holding up sessions or eating all the memory
+const QUEUE_SIZE: usize = 10_000;
+
+// a batch that still fails after this many attempts is dropped
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Summary of a finished session, as sent to sinks.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionEvent {
+    #[serde(skip)]
+    pub started: SystemTime,
+    /// When the session started.
+    pub timestamp: String,
+    pub session_id: String,
+    pub peer_ip: String,
+    pub peer_port: u16,
+    /// Address the client connected to.
+    #[serde(skip)]
+    pub local_addr: SocketAddr,
+    /// terraria or console
+    pub service: &'static str,
+    pub world_name: Option<String>,
+    /// How long the session lasted, in seconds.
+    pub duration: f64,
+    /// completed, or what the session ended with, like timeout or peer_closed
+    pub outcome: &'static str,
+    pub error: Option<String>,
+    pub bytes_sent: Option<u64>,
+    pub bytes_received: Option<u64>,
+    pub version: Option<String>,
+    /// Connected as tModLoader, version is then the tModLoader version.
+    pub tmodloader: bool,
+    /// Fake mods a tModLoader client claimed to have installed.
+    pub mods: Vec<String>,
+    /// Fake mod a tModLoader client asked to download.
+    pub requested_mod: Option<String>,
+    /// Anything sent after the ConnectRequest signature, hex encoded.
+    pub connect_extension: Option<String>,
+    pub password: Option<String>,
+    pub player_name: Option<String>,
+    pub player_uuid: Option<String>,
+    /// classic, mediumcore, hardcore or journey
+    pub difficulty: Option<&'static str>,
+    /// Progression flags the player has set, like used_aegis_crystal.
+    pub player_flags: Vec<&'static str>,
+    /// Anything sent after the known PlayerInfo fields, hex encoded.
+    pub player_extension: Option<String>,
+    /// Client version & player name, as counted towards rare fingerprints.
+    pub fingerprint: Option<String>,
+    pub spawn_x: Option<i16>,
+    pub spawn_y: Option<i16>,
+    pub packets: Option<u32>,
+    pub chat: Vec<String>,
+    pub challenge_passed: Option<bool>,
+    /// Packet ids the client sent back to each capability probe.
+    pub probe_responses: Option<String>,
+    /// How the client reacted to a malformed packet.
+    pub malformed_reaction: Option<String>,
+    /// Logins tried against the console decoy.
+    pub logins: Vec<Login>,
+    /// benign, malicious or unknown, if GreyNoise knows the address.
+    pub greynoise_classification: Option<String>,
+    /// Organisation behind the address, if it's a known scanner.
+    pub greynoise_name: Option<String>,
+    pub greynoise_noise: Option<bool>,
+    pub greynoise_riot: Option<bool>,
+    /// Open ports Shodan's seen on the address.
+    pub shodan_ports: Vec<u16>,
+    pub shodan_tags: Vec<String>,
+    pub shodan_cpes: Vec<String>,
+    pub shodan_vulns: Vec<String>,
+    pub shodan_hostnames: Vec<String>,
+    /// Static labels set with --label, filled in as the event's sent.
+    pub labels: BTreeMap<String, String>,
+}
+
+impl SessionEvent {
+    pub fn new(
+        session: &Session,
+        started: SystemTime,
+        info: &ClientInfo,
+        enriched: &Enriched,
+        result: &Result<(), SessionError>,
+    ) -> Self {
+        let behavior = info.behavior.as_ref();
+        let mods = info.mods.as_ref();
+        let flags = info.player_flags.as_ref();
+        let greynoise = enriched.greynoise.as_ref();
+        let shodan = enriched.shodan.as_ref();
+
+        Self {
+            started,
+            timestamp: humantime::format_rfc3339_micros(started).to_string(),
+            session_id: session.id.clone(),
+            peer_ip: session.peer_addr.ip().to_string(),
+            peer_port: session.peer_addr.port(),
+            local_addr: session.local_addr,
+            service: "terraria",
+            world_name: Some(session.world.name.clone()),
+            duration: started.elapsed().unwrap_or_default().as_secs_f64(),
+            outcome: error::outcome(result),
+            error: result.as_ref().err().map(ToString::to_string),
+            bytes_sent: Some(info.traffic.sent.load(Ordering::Relaxed)),
+            bytes_received: Some(info.traffic.received.load(Ordering::Relaxed)),
+            version: info.version.clone(),
+            tmodloader: info.tmodloader,
+            mods: mods.map(|mods| mods.installed.clone()).unwrap_or_default(),
+            requested_mod: mods.and_then(|mods| mods.requested.clone()),
+            connect_extension: info.connect_extension.as_deref().map(packet::hex),
+            password: info.password.clone(),
+            player_name: info.name.clone(),
+            player_uuid: info.uuid.clone(),
+            difficulty: flags.map(|flags| flags.difficulty),
+            player_flags: flags.map(|flags| flags.flags.clone()).unwrap_or_default(),
+            player_extension: flags
+                .and_then(|flags| flags.extension.as_deref())
+                .map(packet::hex),
+            fingerprint: info.fingerprint.clone(),
+            spawn_x: info.spawn.map(|spawn| spawn.x),
+            spawn_y: info.spawn.map(|spawn| spawn.y),
+            packets: behavior.map(|behavior| behavior.packets.values().sum()),
+            chat: behavior
+                .map(|behavior| behavior.chat.clone())
+                .unwrap_or_default(),
+            challenge_passed: info.challenge.as_ref().map(|challenge| challenge.passed),
+            probe_responses: info.probes.as_ref().map(ProbeResults::summary),
+            malformed_reaction: info.malformed.as_ref().map(Reaction::summary),
+            logins: Vec::new(),
+            greynoise_classification: greynoise.map(|greynoise| greynoise.classification.clone()),
+            greynoise_name: greynoise.and_then(|greynoise| greynoise.name.clone()),
+            greynoise_noise: greynoise.map(|greynoise| greynoise.noise),
+            greynoise_riot: greynoise.map(|greynoise| greynoise.riot),
+            shodan_ports: shodan
+                .map(|shodan| shodan.ports.clone())
+                .unwrap_or_default(),
+            shodan_tags: shodan.map(|shodan| shodan.tags.clone()).unwrap_or_default(),
+            shodan_cpes: shodan.map(|shodan| shodan.cpes.clone()).unwrap_or_default(),
+            shodan_vulns: shodan
+                .map(|shodan| shodan.vulns.clone())
+                .unwrap_or_default(),
+            shodan_hostnames: shodan
+                .map(|shodan| shodan.hostnames.clone())
+                .unwrap_or_default(),
+            labels: BTreeMap::new(),
+        }
+    }
+
+    pub fn console(
+        session_id: &str,
+        peer_addr: SocketAddr,
+        local_addr: SocketAddr,
+        started: SystemTime,
+        logins: Vec<Login>,
+        result: &Result<(), SessionError>,
+    ) -> Self {
+        Self {
+            started,
+            timestamp: humantime::format_rfc3339_micros(started).to_string(),
+            session_id: session_id.to_owned(),
+            peer_ip: peer_addr.ip().to_string(),
+            peer_port: peer_addr.port(),
+            local_addr,
+            service: "console",
+            world_name: None,
+            duration: started.elapsed().unwrap_or_default().as_secs_f64(),
+            outcome: error::outcome(result),
+            error: result.as_ref().err().map(ToString::to_string),
+            bytes_sent: None,
+            bytes_received: None,
+            version: None,
+            tmodloader: false,
+            mods: Vec::new(),
+            requested_mod: None,
+            connect_extension: None,
+            password: None,
+            player_name: None,
+            player_uuid: None,
+            difficulty: None,
+            player_flags: Vec::new(),
+            player_extension: None,
+            fingerprint: None,
+            spawn_x: None,
+            spawn_y: None,
+            packets: None,
+            chat: Vec::new(),
+            challenge_passed: None,
+            probe_responses: None,
+            malformed_reaction: None,
+            logins,
+            greynoise_classification: None,
+            greynoise_name: None,
+            greynoise_noise: None,
+            greynoise_riot: None,
+            shodan_ports: Vec::new(),
+            shodan_tags: Vec::new(),
+            shodan_cpes: Vec::new(),
+            shodan_vulns: Vec::new(),
+            shodan_hostnames: Vec::new(),
+            labels: BTreeMap::new(),
+        }
+    }
+}
+
+/// Somewhere session events can be written to in batches.
+trait Sink: Send + Sync + 'static {
+    fn name(&self) -> &'static str;
+
+    fn write(&self, batch: &[Arc<SessionEvent>])
+        -> impl Future<Output = Result<(), String>> + Send;
+}
+
+/// How a sink's events should be batched up.
+#[derive(Debug, Clone, Copy)]
+struct Batching {
+    size: usize,
+    interval: Duration,
+}
+
+/// Queue in front of a sink, written out in batches by a background task.
+struct Queue {
+    name: &'static str,
+    sender: mpsc::Sender<Arc<SessionEvent>>,
+    status: Arc<Status>,
+    metrics: Arc<Metrics>,
+}
+
+impl Queue {
+    fn spawn(sink: impl Sink, batching: Batching, metrics: Arc<Metrics>) -> Self {
+        let name = sink.name();
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        let status = Arc::new(Status::default());
+
+        tokio::spawn(run(
+            sink,
+            batching,
+            receiver,
+            status.clone(),
+            metrics.clone(),
+        ));
+
+        Self {
+            name,
+            sender,
+            status,
+            metrics,
+        }
+    }
+
+    fn send(&self, event: Arc<SessionEvent>) {
+        if let Err(error) = self.sender.try_send(event) {
+            warn!("Dropping session event for {} sink: {error}", self.name);
+            self.metrics.dropped(self.name, "queue_full", 1);
+        }
+
+        self.metrics
+            .queue_depth(self.name, QUEUE_SIZE - self.sender.capacity());
+    }
+}
+
+/// Whether a sink's writes are currently going through.
+#[derive(Debug, Default)]
+struct Status {
+    /// When the sink started failing, none if its last write went through.
+    failing_since: Mutex<Option<Instant>>,
+}
+
+impl Status {
+    fn failed(&self) {
+        self.failing_since
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Instant::now);
+    }
+
+    fn succeeded(&self) {
+        *self.failing_since.lock().unwrap() = None;
+    }
+
+    /// How long the sink's been failing for, if it is.
+    fn failing_for(&self) -> Option<Duration> {
+        self.failing_since
+            .lock()
+            .unwrap()
+            .map(|since| since.elapsed())
+    }
+}
+
+async fn run(
+    sink: impl Sink,
+    batching: Batching,
+    mut receiver: mpsc::Receiver<Arc<SessionEvent>>,
+    status: Arc<Status>,
+    metrics: Arc<Metrics>,
+) {
+    let mut batch = Vec::with_capacity(batching.size);
+
+    loop {
+        // wait for something to write, then give the rest of the batch a chance to fill up
+        let Some(event) = receiver.recv().await else {
+            break;
+        };
+        batch.push(event);
+
+        let deadline = Instant::now() + batching.interval;
+        while batch.len() < batching.size {
+            match tokio::time::timeout_at(deadline.into(), receiver.recv()).await {
+                Ok(Some(event)) => batch.push(event),
+                Ok(None) | Err(_) => break,
+            }
+        }
+
+        metrics.queue_depth(sink.name(), receiver.len());
+        write(&sink, &batch, &status, &metrics).await;
+        batch.clear();
+    }
+}
+
+async fn write(sink: &impl Sink, batch: &[Arc<SessionEvent>], status: &Status, metrics: &Metrics) {
+    let name = sink.name();
+    let mut backoff = Duration::from_secs(1);
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        let started = Instant::now();
+        let result = sink.write(batch).await;
+        metrics.write_duration.record(
+            started.elapsed().as_secs_f64(),
+            &[
+                KeyValue::new("sink", name),
+                KeyValue::new("outcome", if result.is_ok() { "ok" } else { "error" }),
+            ],
+        );
+
+        match result {
+            Ok(()) => {
+                debug!("Wrote {} session events to {name} sink", batch.len());
+                status.succeeded();
+                return;
+            }
+            Err(error) if attempt < MAX_ATTEMPTS => {
+                warn!("Failed to write to {name} sink, retrying in {backoff:?}: {error}");
+                status.failed();
+                metrics.retries.add(1, &[KeyValue::new("sink", name)]);
+
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            Err(error) => {
+                error!(
+                    "Failed to write to {name} sink, dropping {} session events: {error}",
+                    batch.len()
+                );
+                status.failed();
+                metrics.failures.add(1, &[KeyValue::new("sink", name)]);
+                metrics.dropped(name, "write_failed", batch.len());
+            }
+        }
+    }
+}
+
+struct Metrics {
+    queue_depth: Gauge<u64>,
+    write_duration: Histogram<f64>,
+    retries: Counter<u64>,
+    failures: Counter<u64>,
+    dropped: Counter<u64>,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let meter = opentelemetry::global::meter("bottled_honey");
+
+        Self {
+            queue_depth: meter
+                .u64_gauge("sink.queue.depth")
+                .with_description("Session events waiting to be written to each sink.")
+                .init(),
+            write_duration: meter
+                .f64_histogram("sink.write.duration")
+                .with_description("Time taken by each attempt at writing a batch to a sink.")
+                .with_unit("s")
+                .init(),
+            retries: meter
+                .u64_counter("sink.write.retries")
+                .with_description("Failed batch writes that are going to be retried.")
+                .init(),
+            failures: meter
+                .u64_counter("sink.write.failures")
+                .with_description("Batches dropped after running out of attempts.")
+                .init(),
+            dropped: meter
+                .u64_counter("sink.events.dropped")
+                .with_description("Session events that never made it to a sink, by reason.")
+                .init(),
+        }
+    }
+
+    fn queue_depth(&self, sink: &'static str, depth: usize) {
+        self.queue_depth
+            .record(depth as u64, &[KeyValue::new("sink", sink)]);
+    }
+
+    fn dropped(&self, sink: &'static str, reason: &'static str, events: usize) {
+        self.dropped.add(
+            events as u64,
+            &[KeyValue::new("sink", sink), KeyValue::new("reason", reason)],
+        );
+    }
+}
+
+/// Every configured sink.
+pub struct Sinks {
+    queues: Vec<Queue>,
+    labels: BTreeMap<String, String>,
+}
+
+impl Sinks {
+    pub fn from_args(args: &crate::Args) -> color_eyre::Result<Self> {
+        let metrics = Arc::new(Metrics::new());
+        let mut queues = Vec::new();
+
+        if let Some((clickhouse, batching)) = clickhouse::ClickHouse::from_args(&args.clickhouse) {
+            queues.push(Queue::spawn(clickhouse, batching, metrics.clone()));
+        }
+
+        if let Some((loki, batching)) = loki::Loki::from_args(&args.loki) {
+            queues.push(Queue::spawn(loki, batching, metrics.clone()));
+        }
+
+        if let Some((eventlog, batching)) = eventlog::EventLog::from_args(&args.eventlog)? {
+            queues.push(Queue::spawn(eventlog, batching, metrics.clone()));
+        }
+
+        if let Some((ipfix, batching)) = ipfix::Ipfix::from_args(&args.ipfix)? {
+            queues.push(Queue::spawn(ipfix, batching, metrics.clone()));
+        }
+
+        if let Some((zabbix, batching)) = zabbix::Zabbix::from_args(&args.zabbix) {
+            queues.push(Queue::spawn(zabbix, batching, metrics.clone()));
+        }
+
+        Ok(Self {
+            queues,
+            labels: args.labels.iter().cloned().collect(),
+        })
+    }
+
+    /// Sinks that have been failing for at least `threshold`, with how long for.
+    pub fn failing(&self, threshold: Duration) -> Vec<(&'static str, Duration)> {
+        self.queues
+            .iter()
+            .filter_map(|queue| Some((queue.name, queue.status.failing_for()?)))
+            .filter(|(_, failing_for)| *failing_for >= threshold)
+            .collect()
+    }
+
+    pub fn send(&self, mut event: SessionEvent) {
+        event.labels.clone_from(&self.labels);
+        let event = Arc::new(event);
+        for queue in &self.queues {
+            queue.send(event.clone());
+        }
+    }
+}
@@ -0,0 +1,491 @@
+//! Zabbix sender protocol, for pushing to trapper items.
+//!
+//! Every item is sent to the host set with --zabbix-host, under these keys:
+//!
+//! | key                                           | type             | value                                  |
+//! |-----------------------------------------------|------------------|----------------------------------------|
+//! | `bottled_honey.sessions[<service>]`           | numeric unsigned | sessions since starting                |
+//! | `bottled_honey.outcomes[<service>,<outcome>]` | numeric unsigned | sessions that ended with each outcome  |
+//! | `bottled_honey.bytes[received]`               | numeric unsigned | bytes received from clients            |
+//! | `bottled_honey.bytes[sent]`                   | numeric unsigned | bytes sent back to clients             |
+//! | `bottled_honey.passwords`                     | numeric unsigned | terraria sessions that sent a password |
+//! | `bottled_honey.logins`                        | numeric unsigned | logins tried against the console decoy |
+//! | `bottled_honey.alert[<alert>]`                | text             | message for every alert, as it happens |
+//!
+//! Services are terraria & console, outcomes are completed or the snake case name of what
+//! ended the session, like timeout or peer_closed. Counters are totals since starting so
+//! nothing is lost if a push fails, use a change per second preprocessing step for rates.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tracing::{
+    field::{Field, Visit},
+    warn, Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+
+use super::{Batching, SessionEvent, Sink};
+
+const HEADER: &[u8; 4] = b"ZBXD";
+// protocol version 1, without compression or large packets
+const FLAGS: u8 = 0x01;
+const HEADER_LENGTH: usize = 13;
+
+// the server refuses anything bigger
+const MAX_RESPONSE_LENGTH: u64 = 1024 * 1024;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Parser)]
+pub struct ZabbixArgs {
+    /// Zabbix server.
+    ///
+    /// Push session counters & alerts to trapper items on this Zabbix server or proxy,
+    /// see the sink's docs for every item key.
+    /// (expected format: host:port, usually port 10051)
+    #[arg(
+        id = "zabbix_server",
+        value_name = "SERVER",
+        env = "ZABBIX_SERVER",
+        long = "zabbix-server"
+    )]
+    server: Option<String>,
+
+    /// Zabbix host.
+    ///
+    /// Host name the items belong to, as it's configured in Zabbix.
+    #[arg(
+        id = "zabbix_host",
+        value_name = "HOST",
+        env = "ZABBIX_HOST",
+        long = "zabbix-host",
+        default_value = "bottled_honey"
+    )]
+    host: String,
+
+    /// Zabbix flush interval.
+    ///
+    /// Longest to wait for more sessions before pushing the counters anyway.
+    /// (in seconds)
+    #[arg(
+        id = "zabbix_flush_interval",
+        value_name = "FLUSH_INTERVAL",
+        env = "ZABBIX_FLUSH_INTERVAL",
+        long = "zabbix-flush-interval",
+        default_value_t = 60
+    )]
+    flush_interval: u64,
+}
+
+/// One value for one item, as the sender protocol wants it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Item {
+    host: String,
+    key: String,
+    value: String,
+    clock: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    response: String,
+    #[serde(default)]
+    info: String,
+}
+
+/// Where to push items to.
+#[derive(Debug, Clone)]
+struct Sender {
+    server: String,
+    host: String,
+}
+
+impl Sender {
+    fn from_args(args: &ZabbixArgs) -> Option<Self> {
+        Some(Self {
+            server: args.server.clone()?,
+            host: args.host.clone(),
+        })
+    }
+
+    fn item(&self, key: String, value: impl ToString) -> Item {
+        Item {
+            host: self.host.clone(),
+            key,
+            value: value.to_string(),
+            clock: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+
+    async fn send(&self, items: &[Item]) -> Result<(), String> {
+        let request = json!({
+            "request": "sender data",
+            "data": items,
+        });
+
+        let response = tokio::time::timeout(REQUEST_TIMEOUT, self.exchange(&request.to_string()))
+            .await
+            .map_err(|_| "timed out".to_owned())?
+            .map_err(|error| error.to_string())?;
+
+        let response = serde_json::from_slice::<Response>(&response)
+            .map_err(|error| format!("invalid response: {error}"))?;
+        if response.response != "success" {
+            return Err(format!("server responded with {}", response.response));
+        }
+
+        // failed items aren't configured as trapper items on the server,
+        // retrying won't change that so they're only warned about
+        if let Some(failed) = failed(&response.info).filter(|failed| *failed > 0) {
+            warn!(
+                "Zabbix server rejected {failed} of {} items, are they all trapper items on host {:?}? ({})",
+                items.len(),
+                self.host,
+                response.info
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn exchange(&self, request: &str) -> std::io::Result<Vec<u8>> {
+        let mut stream = TcpStream::connect(&self.server).await?;
+        stream.write_all(&frame(request.as_bytes())).await?;
+
+        // the server closes the connection once it's responded
+        let mut response = Vec::new();
+        stream
+            .take(HEADER_LENGTH as u64 + MAX_RESPONSE_LENGTH)
+            .read_to_end(&mut response)
+            .await?;
+
+        unframe(&response)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid header"))
+    }
+}
+
+/// Running totals of everything counted, only updated once a push has gone through.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Counters(BTreeMap<String, u64>);
+
+impl Counters {
+    fn add(&mut self, key: String, value: u64) {
+        *self.0.entry(key).or_default() += value;
+    }
+
+    /// These counters with the batch's sessions added on.
+    fn with(&self, batch: &[Arc<SessionEvent>]) -> Self {
+        let mut counters = self.clone();
+
+        for event in batch {
+            counters.add(key("bottled_honey.sessions", &[event.service]), 1);
+            counters.add(
+                key("bottled_honey.outcomes", &[event.service, event.outcome]),
+                1,
+            );
+            counters.add(
+                key("bottled_honey.bytes", &["received"]),
+                event.bytes_received.unwrap_or_default(),
+            );
+            counters.add(
+                key("bottled_honey.bytes", &["sent"]),
+                event.bytes_sent.unwrap_or_default(),
+            );
+            counters.add(
+                key("bottled_honey.passwords", &[]),
+                event.password.is_some() as u64,
+            );
+            counters.add(key("bottled_honey.logins", &[]), event.logins.len() as u64);
+        }
+
+        counters
+    }
+}
+
+pub struct Zabbix {
+    sender: Sender,
+    counters: Mutex<Counters>,
+}
+
+impl Zabbix {
+    pub fn from_args(args: &ZabbixArgs) -> Option<(Self, Batching)> {
+        let zabbix = Self {
+            sender: Sender::from_args(args)?,
+            counters: Mutex::default(),
+        };
+
+        // counters are totals so a bigger batch only means fewer pushes
+        let batching = Batching {
+            size: 1000,
+            interval: Duration::from_secs(args.flush_interval),
+        };
+
+        Some((zabbix, batching))
+    }
+}
+
+impl Sink for Zabbix {
+    fn name(&self) -> &'static str {
+        "zabbix"
+    }
+
+    async fn write(&self, batch: &[Arc<SessionEvent>]) -> Result<(), String> {
+        // a failed write is retried with the same batch, so it can't be counted until it's sent
+        let counters = self.counters.lock().unwrap().with(batch);
+
+        let items = counters
+            .0
+            .iter()
+            .map(|(key, value)| self.sender.item(key.clone(), value))
+            .collect::<Vec<_>>();
+        self.sender.send(&items).await?;
+
+        *self.counters.lock().unwrap() = counters;
+        Ok(())
+    }
+}
+
+/// Pushes every alert event as it happens.
+pub struct ZabbixAlerts {
+    sender: Sender,
+}
+
+impl ZabbixAlerts {
+    pub fn from_args(args: &ZabbixArgs) -> Option<Self> {
+        Some(Self {
+            sender: Sender::from_args(args)?,
+        })
+    }
+}
+
+impl<S: Subscriber> Layer<S> for ZabbixAlerts {
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        if event.metadata().fields().field("alert").is_none() {
+            return;
+        }
+
+        let mut alert = Alert::default();
+        event.record(&mut alert);
+        let (Some(name), Some(message)) = (alert.name, alert.message) else {
+            return;
+        };
+
+        // every alert is emitted from a tokio task, so there's always a runtime
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+
+        let item = self
+            .sender
+            .item(key("bottled_honey.alert", &[&name]), message);
+        let sender = self.sender.clone();
+        runtime.spawn(async move {
+            if let Err(error) = sender.send(&[item]).await {
+                warn!("Failed to push alert to Zabbix: {error}");
+            }
+        });
+    }
+}
+
+#[derive(Default)]
+struct Alert {
+    name: Option<String>,
+    message: Option<String>,
+}
+
+impl Visit for Alert {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "alert" {
+            self.name = Some(value.to_owned());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.message = Some(format!("{value:?}"));
+        }
+    }
+}
+
+/// Item key with its parameters, quoted wherever zabbix wouldn't parse them as-is.
+fn key(name: &str, params: &[&str]) -> String {
+    if params.is_empty() {
+        return name.to_owned();
+    }
+
+    let params = params
+        .iter()
+        .map(|param| {
+            if param.contains([',', ']', '"']) || param.starts_with(' ') {
+                format!("\"{}\"", param.replace('"', "\\\""))
+            } else {
+                (*param).to_owned()
+            }
+        })
+        .collect::<Vec<_>>();
+
+    format!("{name}[{}]", params.join(","))
+}
+
+/// Wrap data in the protocol header.
+fn frame(data: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(HEADER_LENGTH + data.len());
+    framed.extend_from_slice(HEADER);
+    framed.push(FLAGS);
+    framed.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    // reserved, only used by compressed packets
+    framed.extend_from_slice(&0u32.to_le_bytes());
+    framed.extend_from_slice(data);
+    framed
+}
+
+/// Data inside a framed response, none if the header's wrong or it's cut short.
+fn unframe(framed: &[u8]) -> Option<&[u8]> {
+    let header = framed.get(..HEADER_LENGTH)?;
+    let data = &framed[HEADER_LENGTH..];
+    if &header[..4] != HEADER || header[4] != FLAGS {
+        return None;
+    }
+
+    let length = u32::from_le_bytes(header[5..9].try_into().ok()?) as usize;
+    data.get(..length)
+}
+
+/// How many items failed, from the server's "processed: 1; failed: 0; ..." summary.
+fn failed(info: &str) -> Option<u64> {
+    info.split(';')
+        .filter_map(|part| part.split_once(':'))
+        .find(|(name, _)| name.trim() == "failed")
+        .and_then(|(_, value)| value.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::console::Login;
+
+    fn event(service: &'static str, outcome: &'static str) -> Arc<SessionEvent> {
+        let peer_addr: SocketAddr = "192.0.2.1:50000".parse().unwrap();
+        let local_addr: SocketAddr = "192.0.2.2:7777".parse().unwrap();
+
+        let mut event = SessionEvent::console(
+            "0123456789abcdef",
+            peer_addr,
+            local_addr,
+            SystemTime::now(),
+            Vec::new(),
+            &Ok(()),
+        );
+        event.service = service;
+        event.outcome = outcome;
+        Arc::new(event)
+    }
+
+    #[test]
+    fn key_without_params() {
+        assert_eq!(key("bottled_honey.logins", &[]), "bottled_honey.logins");
+    }
+
+    #[test]
+    fn key_with_params() {
+        assert_eq!(
+            key("bottled_honey.outcomes", &["terraria", "peer_closed"]),
+            "bottled_honey.outcomes[terraria,peer_closed]"
+        );
+    }
+
+    #[test]
+    fn key_quotes_params_zabbix_would_split() {
+        assert_eq!(
+            key("bottled_honey.alert", &["a,b", "c]", " d", "say \"hi\""]),
+            r#"bottled_honey.alert["a,b","c]"," d","say \"hi\""]"#
+        );
+    }
+
+    #[test]
+    fn frame_round_trips() {
+        let framed = frame(b"{}");
+        assert_eq!(&framed[..5], b"ZBXD\x01");
+        assert_eq!(&framed[5..13], &[2, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(unframe(&framed), Some(&b"{}"[..]));
+    }
+
+    #[test]
+    fn unframe_rejects_bad_responses() {
+        assert_eq!(unframe(b"ZBX"), None);
+        assert_eq!(unframe(b"HTTP/1.1 400 Bad Request"), None);
+
+        let mut cut_short = frame(b"{\"response\":\"success\"}");
+        cut_short.truncate(20);
+        assert_eq!(unframe(&cut_short), None);
+    }
+
+    #[test]
+    fn failed_from_info() {
+        let info = "processed: 5; failed: 2; total: 7; seconds spent: 0.000055";
+        assert_eq!(failed(info), Some(2));
+        assert_eq!(failed("processed: 1; failed: 0; total: 1"), Some(0));
+        assert_eq!(failed(""), None);
+    }
+
+    #[test]
+    fn counters_total_sessions() {
+        let mut login = event("console", "completed");
+        Arc::get_mut(&mut login).unwrap().logins = vec![Login {
+            username: "root".to_owned(),
+            password: "toor".to_owned(),
+        }];
+
+        let mut terraria = event("terraria", "timeout");
+        {
+            let terraria = Arc::get_mut(&mut terraria).unwrap();
+            terraria.bytes_received = Some(100);
+            terraria.bytes_sent = Some(40);
+            terraria.password = Some("hunter2".to_owned());
+        }
+
+        let counters = Counters::default().with(&[login, terraria.clone()]);
+        let counters = counters.with(&[terraria]);
+
+        let expected = [
+            ("bottled_honey.bytes[received]", 200),
+            ("bottled_honey.bytes[sent]", 80),
+            ("bottled_honey.logins", 1),
+            ("bottled_honey.outcomes[console,completed]", 1),
+            ("bottled_honey.outcomes[terraria,timeout]", 2),
+            ("bottled_honey.passwords", 2),
+            ("bottled_honey.sessions[console]", 1),
+            ("bottled_honey.sessions[terraria]", 2),
+        ];
+        let expected = expected
+            .into_iter()
+            .map(|(key, value)| (key.to_owned(), value))
+            .collect();
+
+        assert_eq!(counters, Counters(expected));
+    }
+
+    #[test]
+    fn counters_unchanged_by_with() {
+        let counters = Counters::default();
+        let _ = counters.with(&[event("terraria", "completed")]);
+        assert_eq!(counters, Counters::default());
+    }
+}
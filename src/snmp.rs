@@ -0,0 +1,384 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    time::Instant,
+};
+
+use bytes::{BufMut, BytesMut};
+use clap::Parser;
+use color_eyre::eyre::{Context, Result};
+use tracing::{
+    field::{Field, Visit},
+    Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context as LayerContext, Layer};
+
+// bottledHoneyMIB, see mibs/BOTTLED-HONEY-MIB.txt
+const MIB: [u32; 8] = [1, 3, 6, 1, 4, 1, 32473, 7];
+const NOTIFICATIONS: u32 = 0;
+const OBJECTS: u32 = 1;
+
+const SYS_UP_TIME: [u32; 9] = [1, 3, 6, 1, 2, 1, 1, 3, 0];
+const SNMP_TRAP_OID: [u32; 11] = [1, 3, 6, 1, 6, 3, 1, 1, 4, 1, 0];
+
+const SNMP_V2C: i64 = 1;
+
+// ber tags
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const GAUGE32: u8 = 0x42;
+const TIME_TICKS: u8 = 0x43;
+const SNMPV2_TRAP: u8 = 0xa7;
+
+// longest an SnmpAdminString can be
+const MAX_ADMIN_STRING_LENGTH: usize = 255;
+
+/// Event fields sent along with each trap, with the object each is sent as.
+const ALERT_FIELDS: [(&str, u32); 8] = [
+    ("alert", 1),
+    ("token", 2),
+    ("value", 3),
+    ("peer_ip", 4),
+    ("session_id", 5),
+    ("first_peer_ip", 6),
+    ("known_ips", 7),
+    ("message", 8),
+];
+
+#[derive(Debug, Parser)]
+pub struct SnmpArgs {
+    /// SNMP trap receivers.
+    ///
+    /// Send an SNMPv2c trap to these receivers for every alert, like a honeytoken or
+    /// uuid being replayed from another address. Traps are defined in the
+    /// BOTTLED-HONEY-MIB shipped in mibs/.
+    /// (expected format: ip:port, usually port 162, can be passed multiple times)
+    #[arg(
+        value_name = "RECEIVER",
+        env = "SNMP_TRAP_RECEIVER",
+        long = "snmp-trap-receiver"
+    )]
+    receivers: Vec<SocketAddr>,
+
+    /// SNMP community.
+    #[arg(
+        env = "SNMP_COMMUNITY",
+        long = "snmp-community",
+        default_value = "public"
+    )]
+    community: String,
+}
+
+/// Sends a trap for every alert event.
+pub struct TrapLayer {
+    socket: UdpSocket,
+    receivers: Vec<SocketAddr>,
+    community: String,
+    started: Instant,
+}
+
+impl TrapLayer {
+    pub fn from_args(args: &SnmpArgs) -> Result<Option<Self>> {
+        if args.receivers.is_empty() {
+            return Ok(None);
+        }
+
+        let bind: SocketAddr = if args.receivers.iter().all(SocketAddr::is_ipv4) {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = UdpSocket::bind(bind).wrap_err("Failed to bind SNMP socket")?;
+        // traps are sent from inside whatever logged the alert, never hold it up
+        socket.set_nonblocking(true)?;
+
+        Ok(Some(Self {
+            socket,
+            receivers: args.receivers.clone(),
+            community: args.community.clone(),
+            started: Instant::now(),
+        }))
+    }
+
+    fn trap(&self, alert: &str, fields: &HashMap<&'static str, FieldValue>) -> BytesMut {
+        let notification = match alert {
+            "cross_ip_reuse" => 1,
+            "identity_many_addresses" => 2,
+            _ => 3,
+        };
+
+        let mut varbinds = BytesMut::new();
+        // hundredths of a second
+        let uptime = self.started.elapsed().as_millis() as u64 / 10;
+        varbind(&mut varbinds, &SYS_UP_TIME, |value| {
+            unsigned(value, TIME_TICKS, uptime as u32 as u64)
+        });
+        varbind(&mut varbinds, &SNMP_TRAP_OID, |value| {
+            oid(value, &[&MIB[..], &[NOTIFICATIONS, notification]].concat())
+        });
+
+        for (name, object) in ALERT_FIELDS {
+            let Some(field) = fields.get(name) else {
+                continue;
+            };
+
+            let object = [&MIB[..], &[OBJECTS, object, 0]].concat();
+            varbind(&mut varbinds, &object, |value| match field {
+                FieldValue::Text(text) => tlv(value, OCTET_STRING, admin_string(text)),
+                FieldValue::Number(number) => unsigned(value, GAUGE32, *number),
+            });
+        }
+
+        let mut pdu = BytesMut::new();
+        integer(&mut pdu, fastrand::i32(..) as i64);
+        // error status & index
+        integer(&mut pdu, 0);
+        integer(&mut pdu, 0);
+        tlv(&mut pdu, SEQUENCE, &varbinds);
+
+        let mut message = BytesMut::new();
+        integer(&mut message, SNMP_V2C);
+        tlv(&mut message, OCTET_STRING, self.community.as_bytes());
+        tlv(&mut message, SNMPV2_TRAP, &pdu);
+
+        let mut packet = BytesMut::new();
+        tlv(&mut packet, SEQUENCE, &message);
+        packet
+    }
+}
+
+impl<S: Subscriber> Layer<S> for TrapLayer {
+    fn on_event(&self, event: &Event<'_>, _: LayerContext<'_, S>) {
+        if event.metadata().fields().field("alert").is_none() {
+            return;
+        }
+
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let Some(FieldValue::Text(alert)) = fields.0.get("alert") else {
+            return;
+        };
+
+        let trap = self.trap(alert, &fields.0);
+        for receiver in &self.receivers {
+            // can't log from inside the subscriber, a missed trap just goes missing
+            let _ = self.socket.send_to(&trap, receiver);
+        }
+    }
+}
+
+enum FieldValue {
+    Text(String),
+    Number(u64),
+}
+
+/// The fields of an event that go into a trap.
+#[derive(Default)]
+struct Fields(HashMap<&'static str, FieldValue>);
+
+impl Fields {
+    fn wanted(field: &Field) -> bool {
+        ALERT_FIELDS.iter().any(|(name, _)| *name == field.name())
+    }
+}
+
+impl Visit for Fields {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if Self::wanted(field) {
+            self.0.insert(field.name(), FieldValue::Number(value));
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        if Self::wanted(field) {
+            self.0
+                .insert(field.name(), FieldValue::Number(value.max(0) as u64));
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if Self::wanted(field) {
+            self.0
+                .insert(field.name(), FieldValue::Text(value.to_owned()));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if Self::wanted(field) {
+            self.0
+                .insert(field.name(), FieldValue::Text(format!("{value:?}")));
+        }
+    }
+}
+
+/// Cut `text` down to fit an SnmpAdminString, on a char boundary so it's still utf8.
+fn admin_string(text: &str) -> &[u8] {
+    let mut end = text.len().min(MAX_ADMIN_STRING_LENGTH);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    text[..end].as_bytes()
+}
+
+fn varbind(buffer: &mut BytesMut, name: &[u32], value: impl FnOnce(&mut BytesMut)) {
+    let mut bind = BytesMut::new();
+    oid(&mut bind, name);
+    value(&mut bind);
+    tlv(buffer, SEQUENCE, &bind);
+}
+
+fn tlv(buffer: &mut BytesMut, tag: u8, value: &[u8]) {
+    buffer.put_u8(tag);
+
+    let length = value.len();
+    if length < 0x80 {
+        buffer.put_u8(length as u8);
+    } else {
+        let bytes = length.to_be_bytes();
+        let skip = bytes.iter().take_while(|byte| **byte == 0).count();
+        buffer.put_u8(0x80 | (bytes.len() - skip) as u8);
+        buffer.put_slice(&bytes[skip..]);
+    }
+
+    buffer.put_slice(value);
+}
+
+fn integer(buffer: &mut BytesMut, value: i64) {
+    let bytes = value.to_be_bytes();
+    // drop leading bytes that are just sign extension
+    let mut skip = 0;
+    while skip < bytes.len() - 1
+        && ((bytes[skip] == 0 && bytes[skip + 1] & 0x80 == 0)
+            || (bytes[skip] == 0xff && bytes[skip + 1] & 0x80 != 0))
+    {
+        skip += 1;
+    }
+
+    tlv(buffer, INTEGER, &bytes[skip..]);
+}
+
+/// Unsigned application types, which are integers that can't go negative.
+fn unsigned(buffer: &mut BytesMut, tag: u8, value: u64) {
+    let value = value.min(u32::MAX as u64);
+    let bytes = value.to_be_bytes();
+    let mut skip = bytes.iter().take_while(|byte| **byte == 0).count().min(7);
+    // keep a leading zero if the top bit would make it look negative
+    if bytes[skip] & 0x80 != 0 {
+        skip -= 1;
+    }
+
+    tlv(buffer, tag, &bytes[skip..]);
+}
+
+fn oid(buffer: &mut BytesMut, oid: &[u32]) {
+    let mut encoded = BytesMut::new();
+    encoded.put_u8((oid[0] * 40 + oid[1]) as u8);
+
+    for &arc in &oid[2..] {
+        let mut bytes = Vec::new();
+        let mut arc = arc;
+        loop {
+            bytes.push((arc & 0x7f) as u8);
+            arc >>= 7;
+            if arc == 0 {
+                break;
+            }
+        }
+
+        // base 128, most significant first with the top bit set on all but the last
+        for (index, byte) in bytes.iter().enumerate().rev() {
+            encoded.put_u8(if index == 0 { *byte } else { byte | 0x80 });
+        }
+    }
+
+    tlv(buffer, OBJECT_IDENTIFIER, &encoded);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(write: impl FnOnce(&mut BytesMut)) -> Vec<u8> {
+        let mut buffer = BytesMut::new();
+        write(&mut buffer);
+        buffer.to_vec()
+    }
+
+    #[test]
+    fn tlv_lengths() {
+        assert_eq!(encode(|b| tlv(b, OCTET_STRING, b"hi")), b"\x04\x02hi");
+
+        let long = encode(|b| tlv(b, OCTET_STRING, &[0; 200]));
+        assert_eq!(&long[..3], &[OCTET_STRING, 0x81, 200]);
+        assert_eq!(long.len(), 3 + 200);
+
+        let longer = encode(|b| tlv(b, OCTET_STRING, &[0; 300]));
+        assert_eq!(&longer[..4], &[OCTET_STRING, 0x82, 0x01, 0x2c]);
+        assert_eq!(longer.len(), 4 + 300);
+    }
+
+    #[test]
+    fn integers_are_minimal_twos_complement() {
+        assert_eq!(encode(|b| integer(b, 0)), [INTEGER, 1, 0x00]);
+        assert_eq!(encode(|b| integer(b, 127)), [INTEGER, 1, 0x7f]);
+        assert_eq!(encode(|b| integer(b, 128)), [INTEGER, 2, 0x00, 0x80]);
+        assert_eq!(encode(|b| integer(b, -1)), [INTEGER, 1, 0xff]);
+        assert_eq!(encode(|b| integer(b, -128)), [INTEGER, 1, 0x80]);
+        assert_eq!(encode(|b| integer(b, -129)), [INTEGER, 2, 0xff, 0x7f]);
+        assert_eq!(
+            encode(|b| integer(b, i64::MIN)),
+            [INTEGER, 8, 0x80, 0, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn unsigned_never_look_negative() {
+        assert_eq!(encode(|b| unsigned(b, GAUGE32, 0)), [GAUGE32, 1, 0x00]);
+        assert_eq!(encode(|b| unsigned(b, GAUGE32, 0x7f)), [GAUGE32, 1, 0x7f]);
+        assert_eq!(
+            encode(|b| unsigned(b, GAUGE32, 0x80)),
+            [GAUGE32, 2, 0x00, 0x80]
+        );
+        assert_eq!(
+            encode(|b| unsigned(b, TIME_TICKS, u32::MAX as u64)),
+            [TIME_TICKS, 5, 0x00, 0xff, 0xff, 0xff, 0xff]
+        );
+    }
+
+    #[test]
+    fn unsigned_clamp_to_32_bits() {
+        assert_eq!(
+            encode(|b| unsigned(b, GAUGE32, u64::MAX)),
+            [GAUGE32, 5, 0x00, 0xff, 0xff, 0xff, 0xff]
+        );
+    }
+
+    #[test]
+    fn oids() {
+        assert_eq!(
+            encode(|b| oid(b, &SYS_UP_TIME)),
+            [OBJECT_IDENTIFIER, 8, 0x2b, 6, 1, 2, 1, 1, 3, 0]
+        );
+        // arcs past 127 take more than one byte
+        assert_eq!(
+            encode(|b| oid(b, &MIB)),
+            [OBJECT_IDENTIFIER, 9, 0x2b, 6, 1, 4, 1, 0x81, 0xfd, 0x59, 7]
+        );
+        assert_eq!(
+            encode(|b| oid(b, &[1, 3, u32::MAX])),
+            [OBJECT_IDENTIFIER, 6, 0x2b, 0x8f, 0xff, 0xff, 0xff, 0x7f]
+        );
+    }
+
+    #[test]
+    fn admin_strings_are_capped() {
+        assert_eq!(admin_string("cross_ip_reuse"), b"cross_ip_reuse");
+        assert_eq!(admin_string(&"a".repeat(300)).len(), 255);
+        // 254 is the last char boundary before the cap
+        assert_eq!(admin_string(&"é".repeat(200)).len(), 254);
+    }
+}
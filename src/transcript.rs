@@ -0,0 +1,163 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write as _},
+    path::Path,
+    time::SystemTime,
+};
+
+use bytes::Bytes;
+use serde_json::{json, Map, Value};
+use tracing::{trace, Span};
+
+use crate::{
+    attributes::{self, Recorder},
+    packet::hex,
+};
+
+/// Fields parsed out of a packet.
+///
+/// Recorded onto the current span as usual, but also kept around so they can be
+/// written to the transcript once the packet's been handled. Anything the attribute
+/// filter doesn't allow is left out of both, along with the raw packet since that
+/// would give it away anyway.
+#[derive(Debug)]
+pub struct PacketFields {
+    fields: Map<String, Value>,
+    /// Whether the attribute filter left out a field.
+    denied: bool,
+    /// Span, raw packet & byte limit for a raw packet event, sent once the packet's been
+    /// handled & it's known whether any fields were left out.
+    raw_event: Option<(Span, Bytes, usize)>,
+}
+
+impl PacketFields {
+    pub fn new(raw_event: Option<(Span, Bytes, usize)>) -> Self {
+        Self {
+            fields: Map::new(),
+            denied: false,
+            raw_event,
+        }
+    }
+
+    pub fn record<V>(&mut self, name: &'static str, value: V) -> &mut Self
+    where
+        V: tracing::Value + Into<Value> + Clone,
+    {
+        if attributes::allowed(name) {
+            Recorder::new(&Span::current()).record(name, value.clone());
+            self.fields.insert(name.to_owned(), value.into());
+        } else {
+            self.denied = true;
+        }
+
+        self
+    }
+
+    /// For packets that aren't decoded in the current state, but still carry `name`.
+    /// Only keeps the raw packet out if the field wouldn't have been allowed.
+    pub fn skip(&mut self, name: &'static str) -> &mut Self {
+        self.denied |= !attributes::allowed(name);
+
+        self
+    }
+}
+
+// dropped whether or not the packet was handled successfully, so errors still get their event
+impl Drop for PacketFields {
+    fn drop(&mut self) {
+        if let Some((span, raw, limit)) = self.raw_event.take() {
+            if !self.denied {
+                raw_packet_event(&span, "received", &raw, limit);
+            }
+        }
+    }
+}
+
+/// Attach `raw` to the session's span as a hex encoded event, cut down to `limit` bytes.
+/// Left out if the attribute filter doesn't allow "raw_packet".
+pub fn raw_packet_event(span: &Span, direction: &'static str, raw: &[u8], limit: usize) {
+    if !attributes::allowed("raw_packet") {
+        return;
+    }
+
+    trace!(
+        parent: span,
+        direction,
+        length = raw.len(),
+        truncated = raw.len() > limit,
+        raw = %hex(&raw[..raw.len().min(limit)]),
+        "raw packet"
+    );
+}
+
+/// Full decoded transcript of a session, written as json lines.
+pub struct Transcript {
+    file: BufWriter<File>,
+    /// When the packet currently being handled was received, if there is one.
+    handling: Option<String>,
+    /// Anything sent while handling a packet, written after the packet itself
+    /// so the transcript stays in order.
+    deferred: Vec<Value>,
+}
+
+impl Transcript {
+    pub fn create(directory: &Path, session_id: &str) -> std::io::Result<Self> {
+        let file = File::create(directory.join(format!("{session_id}.jsonl")))?;
+
+        Ok(Self {
+            file: BufWriter::new(file),
+            handling: None,
+            deferred: Vec::new(),
+        })
+    }
+
+    /// Start handling a received packet, should be followed up by [`Self::received`].
+    pub fn receiving(&mut self) {
+        self.handling = Some(timestamp());
+    }
+
+    pub fn received(&mut self, id: u8, name: &str, fields: &mut PacketFields, raw: &[u8]) {
+        let timestamp = self.handling.take().unwrap_or_else(timestamp);
+
+        self.write(json!({
+            "timestamp": timestamp,
+            "direction": "received",
+            "id": id,
+            "packet": name,
+            "fields": std::mem::take(&mut fields.fields),
+            "raw": (!fields.denied).then(|| hex(raw)),
+        }));
+
+        for line in std::mem::take(&mut self.deferred) {
+            self.write(line);
+        }
+    }
+
+    pub fn sent(&mut self, name: &str, raw: &[u8]) {
+        let line = json!({
+            "timestamp": timestamp(),
+            "direction": "sent",
+            "id": raw.get(2),
+            "packet": name,
+            "fields": {},
+            "raw": hex(raw),
+        });
+
+        if self.handling.is_some() {
+            self.deferred.push(line);
+        } else {
+            self.write(line);
+        }
+    }
+
+    fn write(&mut self, line: Value) {
+        // losing a transcript isn't worth dropping the client over
+        if let Err(error) = writeln!(self.file, "{line}") {
+            tracing::warn!("Failed to write to transcript: {error}");
+        }
+    }
+}
+
+fn timestamp() -> String {
+    humantime::format_rfc3339_micros(SystemTime::now()).to_string()
+}
@@ -0,0 +1,83 @@
+use std::{
+    hash::{BuildHasher, DefaultHasher, Hash, Hasher, RandomState},
+    net::IpAddr,
+};
+
+use clap::ValueEnum;
+use color_eyre::eyre::{eyre, Context, Result};
+
+use crate::{
+    packet::{self, World},
+    Args, Engagement,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum WorldSelection {
+    /// Pick a random world for every connection.
+    PerConnection,
+    /// Always give the same address the same world.
+    PerIp,
+}
+
+/// Pool of fake worlds to pick from, so every connection doesn't see the exact same world.
+#[derive(Debug)]
+pub struct WorldPool {
+    names: Vec<String>,
+    selection: WorldSelection,
+    // randomly keyed so different honeypots don't map addresses the same way
+    hasher: RandomState,
+}
+
+impl WorldPool {
+    pub fn from_args(args: &Args) -> Result<Self> {
+        let mut names = args.world_names.clone();
+
+        if let Some(path) = &args.world_names_file {
+            let file = std::fs::read_to_string(path)
+                .wrap_err_with(|| format!("Failed to read world names from {path:?}"))?;
+
+            for name in file.lines().map(str::trim).filter(|line| !line.is_empty()) {
+                packet::check_string_length(name, packet::MAX_STRING_LENGTH)
+                    .map_err(|error| eyre!("World name {name:?} in {path:?} is {error}"))?;
+                names.push(name.to_owned());
+            }
+        }
+
+        if names.is_empty() {
+            names.push("World".to_owned());
+        }
+
+        Ok(Self {
+            names,
+            selection: args.world_selection,
+            hasher: RandomState::new(),
+        })
+    }
+
+    pub fn pick(&self, peer_ip: IpAddr, engagement: Engagement) -> World {
+        let index = match self.selection {
+            WorldSelection::PerConnection => fastrand::usize(..self.names.len()),
+            WorldSelection::PerIp => self.hasher.hash_one(peer_ip) as usize % self.names.len(),
+        };
+
+        let name = &self.names[index];
+
+        // derive the id from the name so each world's identity stays consistent
+        let mut hasher = DefaultHasher::new();
+        name.hash(&mut hasher);
+
+        // huge world so there's plenty to "download"
+        let (width, height) = if engagement == Engagement::Tarpit {
+            (16800, 4800)
+        } else {
+            (4200, 1200)
+        };
+
+        World {
+            name: name.clone(),
+            id: (hasher.finish() >> 33) as i32,
+            width,
+            height,
+        }
+    }
+}